@@ -0,0 +1,365 @@
+use anyhow::{Context, Result};
+use chromiumoxide::{
+    cdp::browser_protocol::{
+        accessibility::{AxNode, AxNodeId, AxPropertyName, AxValue, GetFullAxTreeParams},
+        dom::BackendNodeId,
+        dom_snapshot::{CaptureSnapshotParams, NodeTreeSnapshot},
+    },
+    Page,
+};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    browser::Viewport,
+    interpreter::{
+        add_hidden_markers, fit_to_budget, placement, visibility_marker, Placement, Priority,
+    },
+    DomSnapshot, TranslateOptions,
+};
+
+/// The attribute used to find the DOM nodes behind actionable accessibility nodes.
+const MARKER_ATTRIBUTE: &str = "data-agent-ax";
+
+/// The node type of elements, as opposed to text, comments or shadow roots.
+const ELEMENT_NODE: i64 = 1;
+
+/// Marks the elements behind accessibility nodes with the ids of the latter, in a single round trip.
+///
+/// CDP can only set one attribute per call, so each element is found by its path through the document
+/// instead: its index among the element children of each of its ancestors.
+const MARK_SCRIPT: &str = r"(marks, attribute) => {
+    document.querySelectorAll(`[${attribute}]`).forEach(e => e.removeAttribute(attribute));
+    for (const [id, path] of marks) {
+        path.reduce((node, index) => node?.children[index], document)?.setAttribute(attribute, id);
+    }
+}";
+
+/// Roles the model can act on.
+const ACTIONABLE_ROLES: &[&str] = &[
+    "button",
+    "link",
+    "textbox",
+    "searchbox",
+    "combobox",
+    "listbox",
+    "option",
+    "checkbox",
+    "radio",
+    "switch",
+    "slider",
+    "spinbutton",
+    "menuitem",
+    "menuitemcheckbox",
+    "menuitemradio",
+    "tab",
+    "treeitem",
+];
+
+/// Roles that give the model context, but cannot be acted on.
+const CONTEXT_ROLES: &[&str] = &["heading"];
+
+/// States that are always rendered, because `false` tells the model something.
+const TRISTATE_PROPERTIES: &[AxPropertyName] = &[
+    AxPropertyName::Checked,
+    AxPropertyName::Pressed,
+    AxPropertyName::Selected,
+    AxPropertyName::Expanded,
+];
+
+/// States that are only rendered when they are set.
+const FLAG_PROPERTIES: &[AxPropertyName] = &[
+    AxPropertyName::Disabled,
+    AxPropertyName::Focused,
+    AxPropertyName::Required,
+    AxPropertyName::Readonly,
+    AxPropertyName::Invalid,
+    AxPropertyName::Level,
+];
+
+/// Translates the accessibility tree of the given page into a format GPT-4 can understand.
+///
+/// Unlike [`translate`](crate::translate), this also finds ARIA widgets, `select`s, `textarea`s and
+/// focusable elements with click handlers, and describes each with its role, name, value and state.
+/// The returned snapshot holds the elements behind the ids used in the translation.
+///
+/// # Arguments
+///
+/// * `page` - The page to translate.
+/// * `viewport` - The currently visible portion of the page.
+/// * `options` - Options that control which nodes are included.
+///
+/// # Errors
+///
+/// * If the accessibility tree cannot be read.
+/// * If the actionable nodes cannot be resolved to elements.
+pub async fn translate_accessibility(
+    page: &Page,
+    viewport: &Viewport,
+    options: &TranslateOptions,
+) -> Result<(DomSnapshot, String)> {
+    let nodes = page
+        .execute(GetFullAxTreeParams::default())
+        .await
+        .context("Failed to get the accessibility tree")?
+        .result
+        .nodes;
+
+    let roles: HashMap<_, _> = nodes
+        .iter()
+        .map(|node| (node.node_id.inner().clone(), role(node)))
+        .collect();
+
+    let actionable: Vec<&AxNode> = nodes
+        .iter()
+        .filter(|node| !node.ignored && is_actionable(node))
+        .collect();
+    let actionable_ids: HashSet<_> = actionable.iter().map(|node| node.node_id.inner()).collect();
+    let snapshot = resolve(page, &actionable).await?;
+    let elements: HashMap<_, _> = snapshot
+        .elements
+        .iter()
+        .filter_map(|element| Some((element.attribute(MARKER_ATTRIBUTE)?, element)))
+        .collect();
+
+    let mut summary = Vec::new();
+    let (mut hidden_above, mut hidden_below) = (0, 0);
+
+    for node in nodes.iter().filter(|node| !node.ignored) {
+        let role = role(node);
+        let name = text(node.name.as_ref());
+
+        if let Some(element) = elements.get(node.node_id.inner().as_str()) {
+            if options.viewport_only {
+                match placement(element.bounds.as_ref(), viewport) {
+                    Some(Placement::Near) => {}
+                    Some(Placement::Above) => {
+                        hidden_above += 1;
+                        continue;
+                    }
+                    Some(Placement::Below) => {
+                        hidden_below += 1;
+                        continue;
+                    }
+                    None => continue,
+                }
+            }
+
+            let Some(marker) = visibility_marker(element, options.visibility) else {
+                continue;
+            };
+
+            let mut tag = vec![role.clone(), format!("id={}{marker}", element.id)];
+            let value = text(node.value.as_ref());
+            if !value.is_empty() {
+                tag.push(format!("value=\"{value}\""));
+            }
+            tag.extend(states(node));
+
+            summary.push((
+                Priority::of_role(&role),
+                format!("<{}>{name}</{role}>", tag.join(" ")),
+            ));
+        } else if CONTEXT_ROLES.contains(&role.as_str()) && !name.is_empty() {
+            let mut tag = vec![role.clone()];
+            tag.extend(states(node));
+            summary.push((
+                Priority::of_role(&role),
+                format!("<{}>{name}</{role}>", tag.join(" ")),
+            ));
+        } else if options.include_paragraphs && role == "StaticText" && !name.is_empty() {
+            // Text inside actionable nodes and headings is already part of their name.
+            if let Some(parent) = node.parent_id.as_ref().map(AxNodeId::inner) {
+                let parent_role = roles.get(parent).map_or("", String::as_str);
+                if actionable_ids.contains(parent) || CONTEXT_ROLES.contains(&parent_role) {
+                    continue;
+                }
+            }
+
+            summary.push((Priority::Text, format!("<text>{name}</text>")));
+        }
+    }
+
+    let mut summary = fit_to_budget(summary, options.max_tokens);
+    if options.viewport_only {
+        add_hidden_markers(&mut summary, viewport, hidden_above, hidden_below);
+    }
+
+    Ok((snapshot, summary.join("\n")))
+}
+
+/// Takes a snapshot of the elements behind the given accessibility nodes.
+///
+/// Each element is marked with the id of its accessibility node first, so they can be matched up again.
+async fn resolve(page: &Page, nodes: &[&AxNode]) -> Result<DomSnapshot> {
+    let paths = element_paths(page).await?;
+    let marks: Vec<_> = nodes
+        .iter()
+        .filter_map(|node| Some((node.node_id.inner(), paths.get(&node.backend_dom_node_id?)?)))
+        .collect();
+
+    let script = format!(
+        "({MARK_SCRIPT})({}, {})",
+        serde_json::to_string(&marks)?,
+        serde_json::to_string(MARKER_ATTRIBUTE)?
+    );
+    page.evaluate(script)
+        .await
+        .context("Failed to mark the actionable nodes")?;
+
+    DomSnapshot::capture(page, &format!("[{MARKER_ATTRIBUTE}]")).await
+}
+
+/// The path from the document to each of its elements, keyed by their backend node ids.
+///
+/// Elements in frames, shadow roots and pseudo-elements have no such path, and are left out just like
+/// [`DomSnapshot::capture`] leaves them out.
+async fn element_paths(page: &Page) -> Result<HashMap<BackendNodeId, Vec<usize>>> {
+    let snapshot = page
+        .execute(CaptureSnapshotParams::new(Vec::new()))
+        .await
+        .context("Failed to get the document tree")?
+        .result;
+
+    Ok(snapshot
+        .documents
+        .into_iter()
+        .next()
+        .map(|document| paths_in(document.nodes))
+        .unwrap_or_default())
+}
+
+/// The path from the root of a document snapshot to each of its elements, keyed by their backend node ids.
+fn paths_in(tree: NodeTreeSnapshot) -> HashMap<BackendNodeId, Vec<usize>> {
+    let (Some(parents), Some(types), Some(backend_ids)) =
+        (tree.parent_index, tree.node_type, tree.backend_node_id)
+    else {
+        return HashMap::new();
+    };
+    let pseudo: HashSet<_> = tree
+        .pseudo_type
+        .map(|pseudo| {
+            pseudo
+                .index
+                .into_iter()
+                .filter_map(|index| usize::try_from(index).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // The document comes first, and every other node after its parent.
+    let mut paths: Vec<Option<Vec<usize>>> = Vec::with_capacity(parents.len());
+    let mut element_children = vec![0; parents.len()];
+    for (index, (parent, node_type)) in parents.into_iter().zip(types).enumerate() {
+        let parent = usize::try_from(parent).ok();
+        let path = match parent {
+            None => Some(Vec::new()),
+            Some(parent) if node_type == ELEMENT_NODE && !pseudo.contains(&index) => {
+                let position = element_children[parent];
+                element_children[parent] += 1;
+                paths
+                    .get(parent)
+                    .and_then(Option::as_ref)
+                    .map(|parent_path| {
+                        let mut path = parent_path.clone();
+                        path.push(position);
+                        path
+                    })
+            }
+            Some(_) => None,
+        };
+        paths.push(path);
+    }
+
+    backend_ids
+        .into_iter()
+        .zip(paths)
+        .filter_map(|(backend_id, path)| Some((backend_id, path?)))
+        .collect()
+}
+
+/// Whether the model can act on the given node.
+fn is_actionable(node: &AxNode) -> bool {
+    let role = role(node);
+    if ACTIONABLE_ROLES.contains(&role.as_str()) {
+        return true;
+    }
+
+    // Catches `div`s with click handlers, as long as they can be focused and have something to show.
+    role != "RootWebArea"
+        && !text(node.name.as_ref()).is_empty()
+        && property(node, &AxPropertyName::Focusable).is_some_and(|value| value == "true")
+}
+
+/// The role of the given node, like `button` or `heading`.
+fn role(node: &AxNode) -> String {
+    text(node.role.as_ref())
+}
+
+/// The states of the given node, rendered as attributes.
+fn states(node: &AxNode) -> Vec<String> {
+    let mut states = Vec::new();
+
+    for name in TRISTATE_PROPERTIES {
+        if let Some(value) = property(node, name) {
+            states.push(format!("{}={value}", name.as_ref()));
+        }
+    }
+    for name in FLAG_PROPERTIES {
+        match property(node, name).as_deref() {
+            None | Some("false") => {}
+            Some("true") => states.push(name.as_ref().to_string()),
+            Some(value) => states.push(format!("{}={value}", name.as_ref())),
+        }
+    }
+
+    states
+}
+
+/// The value of the given property of a node, if it has one.
+fn property(node: &AxNode, name: &AxPropertyName) -> Option<String> {
+    node.properties
+        .as_ref()?
+        .iter()
+        .find(|property| &property.name == name)
+        .map(|property| text(Some(&property.value)))
+}
+
+/// The text of an accessibility value, with whitespace collapsed.
+fn text(value: Option<&AxValue>) -> String {
+    let text = match value.and_then(|value| value.value.as_ref()) {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(other) => other.to_string(),
+    };
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chromiumoxide::cdp::browser_protocol::dom_snapshot::RareStringData;
+
+    #[test]
+    fn finds_elements_by_their_index_among_element_children() {
+        // document > html > body > [text, div > [::before, button], shadow root > span, a]
+        let tree = NodeTreeSnapshot {
+            parent_index: Some(vec![-1, 0, 1, 2, 2, 4, 4, 2, 7, 2]),
+            node_type: Some(vec![9, 1, 1, 3, 1, 1, 1, 11, 1, 1]),
+            backend_node_id: Some((0..10).map(BackendNodeId::new).collect()),
+            pseudo_type: Some(RareStringData::new(vec![5], vec![])),
+            ..NodeTreeSnapshot::default()
+        };
+
+        let paths = paths_in(tree);
+        let path = |id| paths.get(&BackendNodeId::new(id)).cloned();
+
+        assert_eq!(path(2), Some(vec![0, 0]));
+        assert_eq!(path(6), Some(vec![0, 0, 0, 0]));
+        assert_eq!(path(9), Some(vec![0, 0, 1]));
+        assert_eq!(path(3), None);
+        assert_eq!(path(5), None);
+        assert_eq!(path(8), None);
+    }
+}
@@ -0,0 +1,716 @@
+use chromiumoxide::{Element, Page};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::str::FromStr;
+use tracing::info;
+use url::Url;
+
+use crate::{browser, DomSnapshot};
+
+/// Actions that can be taken by the browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Click on an element.
+    /// The usize is the id of the element.
+    Click(usize),
+
+    /// Outputs the updated goal, or reports progress towards a goal given by the user.
+    Goal(String),
+
+    /// Finish the run, reporting the result.
+    Done(String),
+
+    /// Type the given text into the given element and press ENTER.
+    /// The usize is the id of the element, and the String is the text to type.
+    Type(usize, String),
+
+    /// Scroll the page by one screen in the given direction.
+    Scroll(ScrollDirection),
+
+    /// Scroll the given element into view.
+    /// The usize is the id of the element.
+    ScrollTo(usize),
+
+    /// Navigate to the given URL.
+    Navigate(Url),
+
+    /// Go back to the previous page in the history.
+    Back,
+
+    /// Go forward to the next page in the history.
+    Forward,
+
+    /// Reload the current page.
+    Reload,
+
+    /// Choose an option in a dropdown.
+    /// The usize is the id of the element, and the String is the text of the option.
+    Select(usize, String),
+
+    /// Check or uncheck a checkbox or radio button.
+    /// The usize is the id of the element, and the bool is whether it should be checked.
+    Check(usize, bool),
+
+    /// Clear the text in an input or text area.
+    /// The usize is the id of the element.
+    Clear(usize),
+
+    /// Act on a different tab.
+    /// The usize is the number of the tab, as listed in the page description.
+    SwitchTab(usize),
+
+    /// Close a tab.
+    /// The usize is the number of the tab, as listed in the page description.
+    CloseTab(usize),
+
+    /// Open the given URL in a new tab, and act on it.
+    NewTab(Url),
+}
+
+/// The direction to scroll the page in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollDirection {
+    /// Towards the top of the page.
+    Up,
+    /// Towards the bottom of the page.
+    Down,
+}
+
+/// The format the model is asked to reply in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// Plain-text commands, like `CLICK 3`.
+    #[default]
+    Text,
+    /// A JSON object matching [`Action::json_schema`].
+    Json,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown protocol \"{s}\", expected \"text\" or \"json\"."
+            )),
+        }
+    }
+}
+
+/// The reasons a reply from the model can fail to parse into an [`Action`].
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    /// The reply was empty.
+    #[error("the reply did not contain a command")]
+    Empty,
+    /// The reply did not contain any known command.
+    #[error("unknown command \"{0}\"")]
+    UnknownCommand(String),
+    /// The reply contained more than one command, so it's unclear which one was meant.
+    #[error("the reply contains more than one command ({}), send exactly one", .0.join(", "))]
+    MultipleCommands(Vec<String>),
+    /// The command is missing one of its arguments.
+    #[error("{command} is missing its {argument}")]
+    MissingArgument {
+        /// The command that was given.
+        command: &'static str,
+        /// The argument that was left out.
+        argument: &'static str,
+    },
+    /// The element id is not a non-negative integer.
+    #[error("\"{0}\" is not a valid element id")]
+    InvalidId(String),
+    /// The tab number is not a non-negative integer.
+    #[error("\"{0}\" is not a valid tab number")]
+    InvalidTab(String),
+    /// The URL could not be parsed.
+    #[error("\"{0}\" is not a valid URL")]
+    InvalidUrl(String),
+    /// The reply looked like JSON, but did not match the schema.
+    #[error("invalid JSON action: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The reasons an action can fail in a way the model can recover from.
+///
+/// These are reported back to the model, so it can choose a different action.
+#[derive(Debug, thiserror::Error)]
+pub enum ActionError {
+    /// The reply could not be parsed into an action.
+    #[error("could not parse your reply: {0}")]
+    Parse(#[from] ParseError),
+    /// The action refers to an element that is not on the page.
+    #[error("element {id} does not exist; {}", valid_ids(ids))]
+    UnknownElement {
+        /// The id of the requested element.
+        id: usize,
+        /// The ids of the elements on the page, in ascending order.
+        ids: Vec<usize>,
+    },
+    /// The action refers to a tab that is not open.
+    #[error("tab {tab} does not exist; there are {count} tabs open, numbered from 0")]
+    UnknownTab {
+        /// The number of the requested tab.
+        tab: usize,
+        /// The number of tabs open.
+        count: usize,
+    },
+    /// The action refers to an element that was removed from the page after it was described.
+    #[error("element {0} is no longer on the page; it was removed or replaced after the page was described")]
+    StaleElement(usize),
+    /// The browser failed to perform the action.
+    #[error("the action failed: {0}")]
+    Failed(String),
+}
+
+impl From<chromiumoxide::error::CdpError> for ActionError {
+    fn from(error: chromiumoxide::error::CdpError) -> Self {
+        Self::Failed(error.to_string())
+    }
+}
+
+impl From<anyhow::Error> for ActionError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::Failed(format!("{error:#}"))
+    }
+}
+
+fn valid_ids(ids: &[usize]) -> String {
+    if ids.is_empty() {
+        return String::from("there are no elements on the page");
+    }
+
+    // Collapse runs of consecutive ids, like "0-4, 7, 9-12".
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &id in ids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == id => *end = id,
+            _ => ranges.push((id, id)),
+        }
+    }
+
+    let ranges: Vec<_> = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect();
+
+    format!("valid ids are {}", ranges.join(", "))
+}
+
+/// The commands understood by the text grammar.
+const COMMANDS: &[&str] = &[
+    "CLICK",
+    "TYPE",
+    "SCROLL",
+    "GOTO",
+    "BACK",
+    "FORWARD",
+    "RELOAD",
+    "SELECT",
+    "CHECK",
+    "UNCHECK",
+    "CLEAR",
+    "SWITCHTAB",
+    "CLOSETAB",
+    "NEWTAB",
+    "GOAL",
+    "DONE",
+];
+
+impl Action {
+    /// Parses a reply from the model, in either protocol.
+    ///
+    /// Replies containing a JSON object are parsed as JSON first, falling back to the text grammar if that fails.
+    ///
+    /// # Errors
+    ///
+    /// * If the reply is not a valid action in either protocol.
+    pub fn parse(reply: &str) -> Result<Self, ParseError> {
+        let json = reply
+            .find('{')
+            .zip(reply.rfind('}'))
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| &reply[start..=end]);
+
+        let Some(json) = json else {
+            return Self::from_text(reply);
+        };
+
+        Self::from_json(json).or_else(|error| Self::from_text(reply).map_err(|_| error))
+    }
+
+    /// Parses a reply written in the text grammar, like `CLICK 3`.
+    ///
+    /// A command at the start of a line is used even if its text mentions other commands. Otherwise, any chatter
+    /// before the command is ignored, as long as the reply mentions no other command.
+    ///
+    /// # Errors
+    ///
+    /// * If the reply does not contain a known command, or the command's arguments are invalid.
+    /// * If the reply contains more than one command.
+    pub fn from_text(reply: &str) -> Result<Self, ParseError> {
+        let first = reply.split_whitespace().next().ok_or(ParseError::Empty)?;
+        let mut parts = command_words(reply)?.into_iter();
+
+        let command = parts
+            .next()
+            .and_then(|part| COMMANDS.iter().copied().find(|c| *c == part))
+            .ok_or_else(|| ParseError::UnknownCommand(first.to_string()))?;
+
+        match command {
+            "CLICK" => Ok(Self::Click(parse_id(argument(
+                &mut parts,
+                command,
+                "element id",
+            )?)?)),
+            "TYPE" => {
+                let id = parse_id(argument(&mut parts, command, "element id")?)?;
+
+                Ok(Self::Type(id, rest(parts)))
+            }
+            "SCROLL" => match argument(&mut parts, command, "direction or element id")? {
+                "UP" => Ok(Self::Scroll(ScrollDirection::Up)),
+                "DOWN" => Ok(Self::Scroll(ScrollDirection::Down)),
+                id => Ok(Self::ScrollTo(parse_id(id)?)),
+            },
+            "GOTO" => Ok(Self::Navigate(parse_url(argument(
+                &mut parts, command, "URL",
+            )?)?)),
+            "BACK" => Ok(Self::Back),
+            "FORWARD" => Ok(Self::Forward),
+            "RELOAD" => Ok(Self::Reload),
+            "SELECT" => {
+                let id = parse_id(argument(&mut parts, command, "element id")?)?;
+
+                Ok(Self::Select(id, rest(parts)))
+            }
+            "CHECK" | "UNCHECK" => Ok(Self::Check(
+                parse_id(argument(&mut parts, command, "element id")?)?,
+                command == "CHECK",
+            )),
+            "CLEAR" => Ok(Self::Clear(parse_id(argument(
+                &mut parts,
+                command,
+                "element id",
+            )?)?)),
+            "SWITCHTAB" => Ok(Self::SwitchTab(parse_tab(argument(
+                &mut parts,
+                command,
+                "tab number",
+            )?)?)),
+            "CLOSETAB" => Ok(Self::CloseTab(parse_tab(argument(
+                &mut parts,
+                command,
+                "tab number",
+            )?)?)),
+            "NEWTAB" => Ok(Self::NewTab(parse_url(argument(
+                &mut parts, command, "URL",
+            )?)?)),
+            "DONE" => Ok(Self::Done(rest(parts))),
+            _ => Ok(Self::Goal(rest(parts))),
+        }
+    }
+
+    /// Parses a reply written as a JSON object matching [`Action::json_schema`].
+    ///
+    /// # Errors
+    ///
+    /// * If the reply is not valid JSON, or does not match the schema.
+    pub fn from_json(reply: &str) -> Result<Self, ParseError> {
+        Ok(match serde_json::from_str(reply)? {
+            JsonAction::Click { id } => Self::Click(id),
+            JsonAction::Type { id, text } => Self::Type(id, text),
+            JsonAction::Scroll { direction } => Self::Scroll(direction),
+            JsonAction::ScrollTo { id } => Self::ScrollTo(id),
+            JsonAction::Goto { url } => Self::Navigate(parse_url(&url)?),
+            JsonAction::Back => Self::Back,
+            JsonAction::Forward => Self::Forward,
+            JsonAction::Reload => Self::Reload,
+            JsonAction::Select { id, option } => Self::Select(id, option),
+            JsonAction::Check { id, checked } => Self::Check(id, checked),
+            JsonAction::Clear { id } => Self::Clear(id),
+            JsonAction::SwitchTab { tab } => Self::SwitchTab(tab),
+            JsonAction::CloseTab { tab } => Self::CloseTab(tab),
+            JsonAction::NewTab { url } => Self::NewTab(parse_url(&url)?),
+            JsonAction::Goal { text } => Self::Goal(text),
+            JsonAction::Done { text } => Self::Done(text),
+        })
+    }
+
+    /// Performs the action on the page, returning the agent's goal or result if it has finished.
+    ///
+    /// Tab actions need all of the agent's tabs, so the [`Agent`](crate::Agent) performs those itself and they fail here.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - The page to perform the action on.
+    /// * `snapshot` - The snapshot the page was described from, used to find elements by id.
+    ///
+    /// # Errors
+    ///
+    /// * If the action refers to an element that doesn't exist.
+    /// * If the browser fails to perform the action.
+    pub async fn execute(
+        self,
+        page: &Page,
+        snapshot: &DomSnapshot,
+    ) -> Result<Option<String>, ActionError> {
+        match self {
+            Self::Click(id) => {
+                let element = snapshot.resolve(page, id).await?;
+
+                info!(
+                    "Clicking on \"{}\".",
+                    element.inner_text().await?.unwrap_or_default()
+                );
+
+                element.click().await?;
+            }
+            Self::Type(id, text) => {
+                let element = snapshot.resolve(page, id).await?;
+
+                info!("Typing \"{}\" into input.", text);
+
+                // Keystrokes go to whichever element has focus, so click the input first.
+                element.click().await?.type_str(text).await?;
+                element.press_key("Enter").await?;
+            }
+            Self::Scroll(direction) => {
+                info!("Scrolling {:?}.", direction);
+
+                browser::scroll(page, direction).await?;
+            }
+            Self::ScrollTo(id) => {
+                let element = snapshot.resolve(page, id).await?;
+
+                info!("Scrolling element {} into view.", id);
+
+                element.scroll_into_view().await?;
+            }
+            Self::Navigate(url) => {
+                info!("Navigating to {}.", url);
+
+                page.goto(url.as_str()).await?;
+            }
+            Self::Back => {
+                info!("Going back.");
+
+                browser::go_back(page).await?;
+            }
+            Self::Forward => {
+                info!("Going forward.");
+
+                browser::go_forward(page).await?;
+            }
+            Self::Reload => {
+                info!("Reloading the page.");
+
+                page.reload().await?;
+            }
+            Self::Select(id, option) => {
+                let element = snapshot.resolve(page, id).await?;
+
+                info!("Selecting \"{}\" in dropdown.", option);
+
+                select(&element, id, &option).await?;
+            }
+            Self::Check(id, checked) => {
+                let element = snapshot.resolve(page, id).await?;
+
+                info!("Setting element {} to checked={}.", id, checked);
+
+                check(&element, id, checked).await?;
+            }
+            Self::Clear(id) => {
+                let element = snapshot.resolve(page, id).await?;
+
+                info!("Clearing element {}.", id);
+
+                clear(&element, id).await?;
+            }
+            Self::SwitchTab(_) | Self::CloseTab(_) | Self::NewTab(_) => {
+                return Err(ActionError::Failed(String::from(
+                    "tabs can only be managed by the agent",
+                )));
+            }
+            Self::Goal(text) | Self::Done(text) => return Ok(Some(text)),
+        }
+
+        Ok(None)
+    }
+
+    /// The JSON schema of the objects accepted by [`Action::from_json`].
+    #[must_use]
+    pub fn json_schema() -> Value {
+        let id = || {
+            (
+                "id",
+                json!({ "type": "integer", "minimum": 0, "description": "The id of the element." }),
+            )
+        };
+        let text = || ("text", json!({ "type": "string" }));
+        let tab = || {
+            (
+                "tab",
+                json!({ "type": "integer", "minimum": 0, "description": "The number of the tab." }),
+            )
+        };
+
+        json!({
+            "oneOf": [
+                schema_variant("click", "Click on an element. You can only click on links, buttons, and inputs.", [id()]),
+                schema_variant("type", "Type the text into the input and press ENTER.", [id(), text()]),
+                schema_variant("scroll", "Scroll the page up or down by one screen.", [("direction", json!({ "enum": ["up", "down"] }))]),
+                schema_variant("scroll_to", "Scroll the element into view.", [id()]),
+                schema_variant("goto", "Navigate to the URL.", [("url", json!({ "type": "string", "format": "uri" }))]),
+                schema_variant("back", "Go back to the previous page.", []),
+                schema_variant("forward", "Go forward to the next page.", []),
+                schema_variant("reload", "Reload the current page.", []),
+                schema_variant("select", "Choose the option with the given text in the dropdown.", [id(), ("option", json!({ "type": "string" }))]),
+                schema_variant("check", "Check or uncheck the checkbox or radio button.", [id(), ("checked", json!({ "type": "boolean" }))]),
+                schema_variant("clear", "Clear the text in the input or text area.", [id()]),
+                schema_variant("switch_tab", "Switch to the tab with the given number.", [tab()]),
+                schema_variant("close_tab", "Close the tab with the given number.", [tab()]),
+                schema_variant("new_tab", "Open the URL in a new tab.", [("url", json!({ "type": "string", "format": "uri" }))]),
+                schema_variant("goal", "Report the goal.", [text()]),
+                schema_variant("done", "Finish, reporting the result.", [text()]),
+            ]
+        })
+    }
+}
+
+impl TryFrom<String> for Action {
+    type Error = ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl FromStr for Action {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// The wire format of [`Action`] in the JSON protocol.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+enum JsonAction {
+    Click { id: usize },
+    Type { id: usize, text: String },
+    Scroll { direction: ScrollDirection },
+    ScrollTo { id: usize },
+    Goto { url: String },
+    Back,
+    Forward,
+    Reload,
+    Select { id: usize, option: String },
+    Check { id: usize, checked: bool },
+    Clear { id: usize },
+    SwitchTab { tab: usize },
+    CloseTab { tab: usize },
+    NewTab { url: String },
+    Goal { text: String },
+    Done { text: String },
+}
+
+/// Chooses the option with the given text (or value) in a dropdown.
+async fn select(element: &Element, id: usize, option: &str) -> Result<(), ActionError> {
+    let option_json = Value::from(option);
+    let selected = call(
+        element,
+        &format!(
+            "const wanted = {option_json}.trim().toLowerCase();
+            const option = Array.from(this.options ?? []).find(o =>
+                o.text.trim().toLowerCase() === wanted || o.value.toLowerCase() === wanted);
+            if (!option) return false;
+            this.value = option.value;
+            this.dispatchEvent(new Event('input', {{ bubbles: true }}));
+            this.dispatchEvent(new Event('change', {{ bubbles: true }}));
+            return true;"
+        ),
+    )
+    .await?;
+
+    if selected != Some(Value::Bool(true)) {
+        return Err(ActionError::Failed(format!(
+            "element {id} is not a dropdown with an option \"{option}\""
+        )));
+    }
+
+    Ok(())
+}
+
+/// Checks or unchecks a checkbox or radio button, if it isn't already.
+async fn check(element: &Element, id: usize, checked: bool) -> Result<(), ActionError> {
+    let is_checked = || call(element, "return this.checked;");
+    let Some(Value::Bool(current)) = is_checked().await? else {
+        return Err(ActionError::Failed(format!(
+            "element {id} is not a checkbox or radio button"
+        )));
+    };
+
+    // Click rather than setting the property, so the page's own handlers run.
+    if current != checked {
+        element.click().await?;
+
+        if is_checked().await? != Some(Value::Bool(checked)) {
+            return Err(ActionError::Failed(format!(
+                "element {id} could not be {}",
+                if checked { "checked" } else { "unchecked" }
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Clears the text in an input or text area.
+async fn clear(element: &Element, id: usize) -> Result<(), ActionError> {
+    let cleared = call(
+        element,
+        "if (!('value' in this) || this instanceof HTMLSelectElement) return false;
+        this.focus();
+        this.value = '';
+        this.dispatchEvent(new Event('input', { bubbles: true }));
+        this.dispatchEvent(new Event('change', { bubbles: true }));
+        return true;",
+    )
+    .await?;
+
+    if cleared != Some(Value::Bool(true)) {
+        return Err(ActionError::Failed(format!(
+            "element {id} is not an input or text area"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Runs the given function body on the element, returning its result.
+async fn call(element: &Element, body: &str) -> Result<Option<Value>, ActionError> {
+    Ok(element
+        .call_js_fn(format!("function() {{ {body} }}"), false)
+        .await?
+        .result
+        .value)
+}
+
+fn schema_variant<const N: usize>(
+    name: &str,
+    description: &str,
+    properties: [(&str, Value); N],
+) -> Value {
+    let required = std::iter::once("action")
+        .chain(properties.iter().map(|(key, _)| *key))
+        .collect::<Vec<_>>();
+
+    let mut schema = serde_json::Map::new();
+    schema.insert("action".to_string(), json!({ "const": name }));
+    schema.extend(properties.map(|(key, value)| (key.to_string(), value)));
+
+    json!({
+        "type": "object",
+        "description": description,
+        "properties": schema,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// The words of a reply, starting at its only command, or none if it doesn't contain one.
+fn command_words(reply: &str) -> Result<Vec<&str>, ParseError> {
+    let is_command = |word: &&str| COMMANDS.contains(word);
+    let multiple = |commands: Vec<&str>| {
+        ParseError::MultipleCommands(commands.into_iter().map(String::from).collect())
+    };
+
+    let command_lines: Vec<_> = reply
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            Some((index, line.split_whitespace().next().filter(is_command)?))
+        })
+        .collect();
+    match command_lines.as_slice() {
+        [] => {}
+        [(line, _)] => {
+            return Ok(reply
+                .lines()
+                .skip(*line)
+                .flat_map(str::split_whitespace)
+                .collect())
+        }
+        _ => {
+            return Err(multiple(
+                command_lines
+                    .into_iter()
+                    .map(|(_, command)| command)
+                    .collect(),
+            ))
+        }
+    }
+
+    let words: Vec<_> = reply.split_whitespace().collect();
+    let commands: Vec<_> = words.iter().copied().filter(is_command).collect();
+    if commands.len() > 1 {
+        return Err(multiple(commands));
+    }
+
+    Ok(words
+        .into_iter()
+        .skip_while(|word| !is_command(word))
+        .collect())
+}
+
+fn argument<'a>(
+    parts: &mut impl Iterator<Item = &'a str>,
+    command: &'static str,
+    argument: &'static str,
+) -> Result<&'a str, ParseError> {
+    parts
+        .next()
+        .ok_or(ParseError::MissingArgument { command, argument })
+}
+
+fn parse_id(id: &str) -> Result<usize, ParseError> {
+    id.trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .parse()
+        .map_err(|_| ParseError::InvalidId(id.to_string()))
+}
+
+fn parse_tab(tab: &str) -> Result<usize, ParseError> {
+    tab.trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .parse()
+        .map_err(|_| ParseError::InvalidTab(tab.to_string()))
+}
+
+fn parse_url(url: &str) -> Result<Url, ParseError> {
+    let url = url.trim_matches('"');
+
+    // Models often leave out the scheme, so assume HTTPS when it's missing.
+    Url::parse(url)
+        .or_else(|_| Url::parse(&format!("https://{url}")))
+        .map_err(|_| ParseError::InvalidUrl(url.to_string()))
+}
+
+fn rest<'a>(parts: impl Iterator<Item = &'a str>) -> String {
+    parts
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches('"')
+        .to_string()
+}
@@ -1,54 +1,487 @@
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, Result};
+use chromiumoxide::{Browser, Page};
+use tokio::time::{timeout_at, Duration, Instant};
+use tracing::{debug, info, warn};
+use url::Url;
 
-/// Actions that can be taken by the browser.
+use crate::{
+    backend::{ChatBackend, ModelConfig, OpenAiBackend, RetryPolicy, RetryingBackend},
+    browser::{self, SettleOptions},
+    tabs::Tabs,
+    translate, translate_accessibility, Action, ActionError, Conversation, DomSnapshot,
+    HistoryPolicy, Protocol, RunReport, TranslateOptions, Translator, SELECTOR,
+};
+
+/// The page the agent starts on, unless told otherwise.
+pub const DEFAULT_START_URL: &str = "https://duckduckgo.com/";
+
+/// The result of a single step of the agent loop.
 #[derive(Debug)]
-pub enum Action {
-    /// Click on an element.
-    /// The usize is the id of the element.
-    Click(usize),
+pub enum StepOutcome {
+    /// The action was performed, and the agent is still working.
+    Performed(Action),
+    /// The action failed, and the error was reported back to the model.
+    Failed(ActionError),
+    /// The model reported its goal or result, and the run is over.
+    Finished(String),
+    /// The agent took as many steps as it was allowed to.
+    StepLimitReached,
+    /// The agent ran for as long as it was allowed to.
+    TimedOut,
+    /// The model used as many tokens as it was allowed to.
+    TokenBudgetExhausted,
+}
 
-    /// Outputs the update goal.
-    Goal(String),
+impl StepOutcome {
+    /// Whether the run is over, and no more steps should be taken.
+    #[must_use]
+    pub const fn is_final(&self) -> bool {
+        matches!(
+            self,
+            Self::Finished(_)
+                | Self::StepLimitReached
+                | Self::TimedOut
+                | Self::TokenBudgetExhausted
+        )
+    }
+}
 
-    /// Type the given text into the given element and press ENTER.
-    /// The usize is the id of the element, and the String is the text to type.
-    Type(usize, String),
+/// An agent that controls a browser tab to achieve a goal, asking a language model for each action.
+#[derive(Debug)]
+pub struct Agent {
+    /// The browser the agent is controlling.
+    browser: Browser,
+    /// Whether the agent launched the browser, and closes it when it's done.
+    owns_browser: bool,
+    /// The tabs the agent has open, and the one it is acting on.
+    tabs: Tabs,
+    /// The conversation with the model.
+    conversation: Conversation,
+    /// Options that control how pages are described to the model.
+    translate_options: TranslateOptions,
+    /// The maximum number of steps to take, if any.
+    max_steps: Option<usize>,
+    /// When the run must end by, if ever.
+    deadline: Option<Instant>,
+    /// The maximum number of tokens to use across all requests, if any.
+    max_total_tokens: Option<u64>,
+    /// The number of consecutive failed actions to tolerate before giving up.
+    max_errors: usize,
+    /// The number of steps taken so far.
+    steps: usize,
+    /// The number of consecutive failed actions so far.
+    failures: usize,
 }
 
-impl TryFrom<String> for Action {
-    type Error = anyhow::Error;
+impl Agent {
+    /// Start configuring a new agent.
+    #[must_use]
+    pub fn builder() -> AgentBuilder {
+        AgentBuilder::default()
+    }
+
+    /// The browser the agent is controlling.
+    #[must_use]
+    pub const fn browser(&self) -> &Browser {
+        &self.browser
+    }
+
+    /// The tab the agent is acting on.
+    #[must_use]
+    pub fn page(&self) -> &Page {
+        self.tabs.active()
+    }
+
+    /// Every tab the agent has open, including the popups opened from them, in the order they were opened.
+    pub fn pages(&self) -> impl Iterator<Item = &Page> {
+        self.tabs.pages()
+    }
+
+    /// The conversation with the model.
+    #[must_use]
+    pub const fn conversation(&self) -> &Conversation {
+        &self.conversation
+    }
+
+    /// The number of steps taken so far.
+    #[must_use]
+    pub const fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// The steps taken so far, the tokens they used, and what they cost.
+    #[must_use]
+    pub fn report(&self) -> RunReport {
+        RunReport::new(self.steps, &self.conversation)
+    }
+
+    /// Take steps until the model reports its goal or one of the limits is reached.
+    ///
+    /// # Errors
+    ///
+    /// * If a step fails, see [`Agent::step`].
+    pub async fn run(&mut self) -> Result<StepOutcome> {
+        loop {
+            let outcome = self.step().await?;
+
+            if outcome.is_final() {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    /// Describe the current page to the model, and perform the action it replies with.
+    ///
+    /// Failed actions are reported back to the model, so it can try something else on the next step.
+    /// The limits are checked before the step is taken, except for the timeout which can also interrupt it.
+    ///
+    /// # Errors
+    ///
+    /// * If the page cannot be read or translated.
+    /// * If the model cannot be reached.
+    /// * If more consecutive actions fail than the error budget allows.
+    pub async fn step(&mut self) -> Result<StepOutcome> {
+        if let Some(outcome) = self.check_limits() {
+            return Ok(outcome);
+        }
+        self.steps += 1;
+
+        match self.deadline {
+            Some(deadline) => timeout_at(deadline, self.take_step())
+                .await
+                .unwrap_or(Ok(StepOutcome::TimedOut)),
+            None => self.take_step().await,
+        }
+    }
+
+    fn check_limits(&self) -> Option<StepOutcome> {
+        if self.max_steps.is_some_and(|max| self.steps >= max) {
+            return Some(StepOutcome::StepLimitReached);
+        }
+
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Some(StepOutcome::TimedOut);
+        }
+
+        let used = u64::from(self.conversation.usage().total_tokens());
+        if self.max_total_tokens.is_some_and(|max| used >= max) {
+            return Some(StepOutcome::TokenBudgetExhausted);
+        }
+
+        None
+    }
 
-    fn try_from(value: String) -> Result<Self> {
-        let mut parts = value.split_whitespace();
-        let command = parts.next().ok_or_else(|| anyhow!("No command."))?;
+    async fn take_step(&mut self) -> Result<StepOutcome> {
+        self.tabs.settle(&self.browser).await?;
+        let page = self.tabs.active().clone();
 
-        match command {
-            "CLICK" => {
-                let id = parts.next().unwrap().parse()?;
+        let url = page
+            .url()
+            .await?
+            .ok_or_else(|| anyhow!("Page should have a URL."))?;
+        info!("Current URL: {}", url);
 
-                Ok(Self::Click(id))
+        let viewport = browser::viewport(&page).await?;
+        let (snapshot, page_content) = match self.translate_options.translator {
+            Translator::Dom => {
+                let snapshot = DomSnapshot::capture(&page, SELECTOR).await?;
+                let page_content = translate(&snapshot, &viewport, &self.translate_options);
+                (snapshot, page_content)
+            }
+            Translator::Accessibility => {
+                translate_accessibility(&page, &viewport, &self.translate_options).await?
             }
-            "TYPE" => {
-                let id = parts.next().unwrap().parse()?;
+        };
+        debug!("Found {} elements.", snapshot.len());
+        let page_content = format!("{}{page_content}", self.tabs.describe().await?);
+
+        let request = self.conversation.request_action(&url, &page_content).await;
+        if let Some(usage) = self.conversation.step_usage().last() {
+            debug!(
+                "Step {} used {} tokens ({} prompt, {} completion).",
+                self.steps,
+                usage.total_tokens(),
+                usage.prompt_tokens,
+                usage.completion_tokens
+            );
+        }
+
+        let outcome = match request {
+            Ok(action) => self
+                .perform(action.clone(), &page, &snapshot)
+                .await
+                .map(|goal| goal.map_or(StepOutcome::Performed(action), StepOutcome::Finished)),
+            Err(error) => Err(error.downcast::<ActionError>()?),
+        };
 
-                let text = parts
-                    .collect::<Vec<_>>()
-                    .join(" ")
-                    .trim_matches('"')
-                    .to_string();
+        match outcome {
+            Ok(outcome) => {
+                self.failures = 0;
+                Ok(outcome)
+            }
+            Err(error) if self.failures < self.max_errors => {
+                self.failures += 1;
+                warn!(
+                    "Action failed ({}/{}): {error}",
+                    self.failures, self.max_errors
+                );
 
-                Ok(Self::Type(id, text))
+                self.conversation.report_error(&error);
+                Ok(StepOutcome::Failed(error))
             }
-            "GOAL" => {
-                let text = parts
-                    .collect::<Vec<_>>()
-                    .join(" ")
-                    .trim_matches('"')
-                    .to_string();
-
-                Ok(Self::Goal(text))
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Performs an action, on the tabs if it's a tab action or on the given page otherwise.
+    async fn perform(
+        &mut self,
+        action: Action,
+        page: &Page,
+        snapshot: &DomSnapshot,
+    ) -> Result<Option<String>, ActionError> {
+        match action {
+            Action::SwitchTab(index) => self.tabs.switch(index).await.map(|()| None),
+            Action::CloseTab(index) => self.tabs.close(index).await.map(|()| None),
+            Action::NewTab(url) => self.tabs.open_tab(&self.browser, &url).await.map(|()| None),
+            // The conversation records progress towards a fixed goal, and the run carries on.
+            Action::Goal(_) if self.conversation.is_goal_locked() => Ok(None),
+            action => action.execute(page, snapshot).await,
+        }
+    }
+
+    /// Close the browser, or just the agent's tabs if the browser was attached with [`AgentBuilder::attached_browser`].
+    ///
+    /// # Errors
+    ///
+    /// * If the browser (or tab) cannot be closed.
+    pub async fn close(mut self) -> Result<()> {
+        if self.owns_browser {
+            shut_down(&mut self.browser).await?;
+        } else {
+            for page in self.tabs.pages() {
+                page.clone().close().await?;
             }
-            _ => bail!("Unknown command, got {command}"),
         }
+
+        Ok(())
+    }
+}
+
+/// Configures and creates an [`Agent`].
+#[derive(Debug)]
+pub struct AgentBuilder {
+    browser: Option<Browser>,
+    owns_browser: bool,
+    backend: Box<dyn ChatBackend>,
+    goal: Option<String>,
+    protocol: Protocol,
+    start_url: Option<Url>,
+    max_steps: Option<usize>,
+    timeout: Option<Duration>,
+    max_total_tokens: Option<u64>,
+    max_errors: usize,
+    translate_options: TranslateOptions,
+    history_policy: HistoryPolicy,
+    retry_policy: RetryPolicy,
+    model_config: ModelConfig,
+    settle_options: SettleOptions,
+}
+
+impl Default for AgentBuilder {
+    fn default() -> Self {
+        Self {
+            browser: None,
+            owns_browser: true,
+            backend: Box::new(OpenAiBackend::default()),
+            goal: None,
+            protocol: Protocol::default(),
+            start_url: None,
+            max_steps: None,
+            timeout: None,
+            max_total_tokens: None,
+            max_errors: 3,
+            translate_options: TranslateOptions::default(),
+            history_policy: HistoryPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            model_config: ModelConfig::default(),
+            settle_options: SettleOptions::default(),
+        }
+    }
+}
+
+impl AgentBuilder {
+    /// The browser to control, usually from [`browser::init`]. Either this or [`AgentBuilder::attached_browser`] is required.
+    ///
+    /// The browser is closed along with the agent.
+    #[must_use]
+    pub fn browser(mut self, browser: Browser) -> Self {
+        self.browser = Some(browser);
+        self.owns_browser = true;
+        self
+    }
+
+    /// A browser started by someone else to control, usually from [`browser::connect`].
+    ///
+    /// The agent works in a new tab, and only closes its own tabs when it's done, leaving the browser running.
+    #[must_use]
+    pub fn attached_browser(mut self, browser: Browser) -> Self {
+        self.browser = Some(browser);
+        self.owns_browser = false;
+        self
+    }
+
+    /// The backend used to talk to the model. Defaults to GPT-4.
+    #[must_use]
+    pub fn backend(mut self, backend: impl ChatBackend + 'static) -> Self {
+        self.backend = Box::new(backend);
+        self
+    }
+
+    /// A fixed goal for the agent. Without one, the agent sets (and keeps changing) its own goal.
+    #[must_use]
+    pub fn goal(mut self, goal: impl Into<String>) -> Self {
+        self.goal = Some(goal.into());
+        self
+    }
+
+    /// The format the model is asked to reply in.
+    #[must_use]
+    pub const fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// The page the agent starts on. Defaults to `DuckDuckGo`.
+    #[must_use]
+    pub fn start_url(mut self, start_url: Url) -> Self {
+        self.start_url = Some(start_url);
+        self
+    }
+
+    /// The maximum number of steps to take. Unlimited by default.
+    #[must_use]
+    pub const fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// How long the agent may run for, starting when it is built. Unlimited by default.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The maximum number of tokens to use across all requests. Unlimited by default.
+    ///
+    /// Only backends that report their usage count towards this budget.
+    #[must_use]
+    pub const fn max_total_tokens(mut self, max_total_tokens: u64) -> Self {
+        self.max_total_tokens = Some(max_total_tokens);
+        self
+    }
+
+    /// The number of consecutive failed actions to tolerate before giving up. Defaults to 3.
+    #[must_use]
+    pub const fn max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Options that control how pages are described to the model.
+    #[must_use]
+    pub const fn translate_options(mut self, translate_options: TranslateOptions) -> Self {
+        self.translate_options = translate_options;
+        self
+    }
+
+    /// The policy that decides which earlier messages are sent to the model with each request.
+    #[must_use]
+    pub const fn history_policy(mut self, history_policy: HistoryPolicy) -> Self {
+        self.history_policy = history_policy;
+        self
+    }
+
+    /// How often, and how long apart, failed requests to the model are retried. Defaults to 3 retries.
+    #[must_use]
+    pub const fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// The model, and the sampling settings, used for every request.
+    #[must_use]
+    pub fn model_config(mut self, model_config: ModelConfig) -> Self {
+        self.model_config = model_config;
+        self
+    }
+
+    /// Decides when a page has settled after an action. Defaults to waiting up to 10 seconds.
+    #[must_use]
+    pub const fn settle_options(mut self, settle_options: SettleOptions) -> Self {
+        self.settle_options = settle_options;
+        self
+    }
+
+    /// Open the start page and create the agent.
+    ///
+    /// # Errors
+    ///
+    /// * If no browser was given.
+    /// * If the start page cannot be opened, or its network events listened to. A browser given with
+    ///   [`AgentBuilder::browser`] is closed first.
+    pub async fn build(self) -> Result<Agent> {
+        let mut browser = self
+            .browser
+            .ok_or_else(|| anyhow!("The agent needs a browser to control."))?;
+
+        let start_url = self
+            .start_url
+            .map_or_else(|| String::from(DEFAULT_START_URL), String::from);
+        let tabs = match Tabs::open(&browser, &start_url, self.settle_options).await {
+            Ok(tabs) => tabs,
+            Err(error) => {
+                if self.owns_browser {
+                    if let Err(close_error) = shut_down(&mut browser).await {
+                        debug!("Failed to close the browser after the start page failed: {close_error}");
+                    }
+                }
+                return Err(error);
+            }
+        };
+
+        let conversation = self
+            .goal
+            .map_or_else(Conversation::new, Conversation::with_goal)
+            .with_backend(RetryingBackend::new(self.backend, self.retry_policy))
+            .with_model_config(self.model_config)
+            .with_protocol(self.protocol)
+            .with_history_policy(self.history_policy);
+
+        Ok(Agent {
+            browser,
+            owns_browser: self.owns_browser,
+            tabs,
+            conversation,
+            translate_options: self.translate_options,
+            max_steps: self.max_steps,
+            deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+            max_total_tokens: self.max_total_tokens,
+            max_errors: self.max_errors,
+            steps: 0,
+            failures: 0,
+        })
     }
 }
+
+/// Closes a browser the agent launched, and waits for it to exit so its profile can be deleted straight away.
+async fn shut_down(browser: &mut Browser) -> Result<()> {
+    browser.close().await?;
+    browser.wait().await?;
+
+    Ok(())
+}
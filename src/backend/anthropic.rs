@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    retry::{check_status, send_error},
+    ChatBackend, Completion, Message, ModelConfig, Role, Usage,
+};
+
+/// The version of the Messages API this backend speaks.
+const API_VERSION: &str = "2023-06-01";
+
+/// A backend for the Anthropic Messages API.
+#[derive(Debug, Clone)]
+pub struct AnthropicBackend {
+    /// The HTTP client used to communicate with the API.
+    client: reqwest::Client,
+    /// The base URL of the API.
+    base_url: String,
+    /// The key used to authenticate with the API.
+    api_key: String,
+    /// The model to use.
+    model: String,
+}
+
+impl AnthropicBackend {
+    /// Create a backend for the Anthropic API, using the key in `ANTHROPIC_API_KEY`.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model to use.
+    #[must_use]
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: String::from("https://api.anthropic.com/v1"),
+            api_key: std::env::var("ANTHROPIC_API_KEY").unwrap_or_default(),
+            model: model.into(),
+        }
+    }
+
+    /// Send requests to a different server implementing the Messages API.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The base URL of the API, like `https://api.anthropic.com/v1`.
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Use a different API key than the one in `ANTHROPIC_API_KEY`.
+    #[must_use]
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = api_key.into();
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    model: &'a str,
+    system: String,
+    messages: Vec<Message>,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop_sequences: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct Response {
+    content: Vec<ContentBlock>,
+    usage: Option<ResponseUsage>,
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResponseUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+#[async_trait]
+impl ChatBackend for AnthropicBackend {
+    async fn complete(&self, messages: &[Message], config: &ModelConfig) -> Result<Completion> {
+        // The Messages API has no seed, so `config.seed` is ignored.
+        let system = messages
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .map(|message| message.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        // The API expects user and assistant turns to alternate, so merge consecutive messages from the same author.
+        let mut turns: Vec<Message> = Vec::new();
+        for message in messages.iter().filter(|m| m.role != Role::System) {
+            match turns.last_mut() {
+                Some(last) if last.role == message.role => {
+                    last.content.push_str("\n\n");
+                    last.content.push_str(&message.content);
+                }
+                _ => turns.push(message.clone()),
+            }
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/messages", self.base_url.trim_end_matches('/')))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&Request {
+                model: config.model.as_deref().unwrap_or(&self.model),
+                system,
+                messages: turns,
+                max_tokens: config.max_tokens,
+                temperature: config.temperature,
+                top_p: config.top_p,
+                stop_sequences: &config.stop,
+            })
+            .send()
+            .await
+            .map_err(send_error)?;
+
+        let response = check_status(response)
+            .await?
+            .json::<Response>()
+            .await
+            .context("Failed to parse the response from Anthropic.")?;
+
+        let content = response
+            .content
+            .into_iter()
+            .find_map(|block| block.text)
+            .ok_or_else(|| anyhow!("No text returned from Anthropic."))?;
+
+        Ok(Completion {
+            content,
+            usage: response.usage.map(|usage| Usage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            }),
+        })
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+}
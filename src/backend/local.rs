@@ -0,0 +1,118 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    retry::{check_status, send_error},
+    ChatBackend, Completion, Message, ModelConfig, Usage,
+};
+
+/// A backend for a locally hosted model, served by Ollama or anything speaking its `/api/chat` API.
+#[derive(Debug, Clone)]
+pub struct LocalBackend {
+    /// The HTTP client used to communicate with the server.
+    client: reqwest::Client,
+    /// The base URL of the server.
+    base_url: String,
+    /// The model to use.
+    model: String,
+}
+
+impl LocalBackend {
+    /// Create a backend for a server running on the default Ollama port.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model to use.
+    #[must_use]
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: String::from("http://localhost:11434"),
+            model: model.into(),
+        }
+    }
+
+    /// Send requests to a server running somewhere else.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The base URL of the server, like `http://localhost:11434`.
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    stream: bool,
+    options: Options<'a>,
+}
+
+#[derive(Serialize)]
+struct Options<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    num_predict: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct Response {
+    message: Message,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+}
+
+#[async_trait]
+impl ChatBackend for LocalBackend {
+    async fn complete(&self, messages: &[Message], config: &ModelConfig) -> Result<Completion> {
+        let response = self
+            .client
+            .post(format!("{}/api/chat", self.base_url.trim_end_matches('/')))
+            .json(&Request {
+                model: config.model.as_deref().unwrap_or(&self.model),
+                messages,
+                stream: false,
+                options: Options {
+                    temperature: config.temperature,
+                    top_p: config.top_p,
+                    num_predict: config.max_tokens,
+                    seed: config.seed,
+                    stop: &config.stop,
+                },
+            })
+            .send()
+            .await
+            .map_err(send_error)?;
+
+        let response = check_status(response)
+            .await?
+            .json::<Response>()
+            .await
+            .context("Failed to parse the response from the local server.")?;
+
+        Ok(Completion {
+            content: response.message.content,
+            usage: response.prompt_eval_count.zip(response.eval_count).map(
+                |(prompt_tokens, completion_tokens)| Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                },
+            ),
+        })
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use super::{ChatBackend, Completion, Message, ModelConfig, Usage};
+
+type Responder = dyn Fn(&[Message]) -> String + Send + Sync;
+
+/// Where a [`MockBackend`] gets its replies from.
+enum Replies {
+    /// A fixed list of replies, returned in order.
+    Scripted(VecDeque<String>),
+    /// A function of the messages so far.
+    Computed(Box<Responder>),
+}
+
+/// A deterministic backend that never touches the network, for tests and offline development.
+///
+/// Clones share their state, so a clone can be kept around to inspect the requests made through a [`Conversation`](crate::Conversation).
+#[derive(Clone)]
+pub struct MockBackend {
+    /// The source of replies.
+    replies: Arc<Mutex<Replies>>,
+    /// Every request made so far.
+    requests: Arc<Mutex<Vec<Vec<Message>>>>,
+}
+
+impl MockBackend {
+    /// Create a backend that replies with each of the given messages in turn, and fails once they run out.
+    ///
+    /// # Arguments
+    ///
+    /// * `replies` - The replies to return.
+    #[must_use]
+    pub fn scripted(replies: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self::with_replies(Replies::Scripted(
+            replies.into_iter().map(Into::into).collect(),
+        ))
+    }
+
+    /// Create a backend that computes each reply from the messages so far.
+    ///
+    /// # Arguments
+    ///
+    /// * `responder` - Called with the messages of every request, returns the reply.
+    #[must_use]
+    pub fn from_fn(responder: impl Fn(&[Message]) -> String + Send + Sync + 'static) -> Self {
+        Self::with_replies(Replies::Computed(Box::new(responder)))
+    }
+
+    fn with_replies(replies: Replies) -> Self {
+        Self {
+            replies: Arc::new(Mutex::new(replies)),
+            requests: Arc::default(),
+        }
+    }
+
+    /// The messages of every request made so far, in order.
+    ///
+    /// # Panics
+    ///
+    /// * If another thread panicked while holding the lock.
+    #[must_use]
+    pub fn requests(&self) -> Vec<Vec<Message>> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl fmt::Debug for MockBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockBackend")
+            .field("requests", &self.requests.lock().map(|r| r.len()))
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl ChatBackend for MockBackend {
+    async fn complete(&self, messages: &[Message], _config: &ModelConfig) -> Result<Completion> {
+        self.requests
+            .lock()
+            .map_err(|_| anyhow!("The mock backend was poisoned."))?
+            .push(messages.to_vec());
+
+        let content = match &mut *self
+            .replies
+            .lock()
+            .map_err(|_| anyhow!("The mock backend was poisoned."))?
+        {
+            Replies::Scripted(replies) => replies
+                .pop_front()
+                .ok_or_else(|| anyhow!("The mock backend ran out of scripted replies."))?,
+            Replies::Computed(responder) => responder(messages),
+        };
+
+        // Count words rather than tokens, so usage stays deterministic without a tokenizer.
+        let words = |text: &str| u32::try_from(text.split_whitespace().count()).unwrap_or(u32::MAX);
+
+        Ok(Completion {
+            usage: Some(Usage {
+                prompt_tokens: messages.iter().map(|m| words(&m.content)).sum(),
+                completion_tokens: words(&content),
+            }),
+            content,
+        })
+    }
+}
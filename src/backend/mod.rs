@@ -0,0 +1,157 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, ops::AddAssign};
+
+mod anthropic;
+mod local;
+mod mock;
+mod openai;
+mod pricing;
+mod retry;
+
+pub use anthropic::AnthropicBackend;
+pub use local::LocalBackend;
+pub use mock::MockBackend;
+pub use openai::OpenAiBackend;
+pub use pricing::Price;
+pub use retry::{RetryPolicy, RetryingBackend, TransientError};
+
+/// The model, and the sampling settings, used for every request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelConfig {
+    /// The model to use, instead of the one the backend was created with.
+    pub model: Option<String>,
+    /// The sampling temperature, or `None` for the server's default. Lower values make runs easier to reproduce.
+    pub temperature: Option<f32>,
+    /// The nucleus sampling probability mass, or `None` for the server's default.
+    pub top_p: Option<f32>,
+    /// The maximum number of tokens generated for each reply.
+    pub max_tokens: u32,
+    /// The seed for sampling, for backends that support one (`OpenAI` and local servers).
+    pub seed: Option<u64>,
+    /// Sequences that end the reply when the model generates them.
+    pub stop: Vec<String>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            model: None,
+            temperature: Some(0.7),
+            top_p: None,
+            max_tokens: 500,
+            seed: None,
+            stop: Vec::new(),
+        }
+    }
+}
+
+/// A language model that can continue a chat.
+#[async_trait]
+pub trait ChatBackend: Debug + Send + Sync {
+    /// Generate the next message in the chat.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The messages so far, starting with the system prompt.
+    /// * `config` - The model, and the sampling settings, to use.
+    ///
+    /// # Errors
+    ///
+    /// * If the request fails, or the response doesn't contain a message.
+    async fn complete(&self, messages: &[Message], config: &ModelConfig) -> Result<Completion>;
+
+    /// The name of the model requests are sent to, if there is one. Used to estimate the cost of a run.
+    fn model(&self) -> Option<&str> {
+        None
+    }
+}
+
+#[async_trait]
+impl<T: ChatBackend + ?Sized> ChatBackend for Box<T> {
+    async fn complete(&self, messages: &[Message], config: &ModelConfig) -> Result<Completion> {
+        (**self).complete(messages, config).await
+    }
+
+    fn model(&self) -> Option<&str> {
+        (**self).model()
+    }
+}
+
+/// The author of a message in a chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Instructions for the model.
+    System,
+    /// The agent loop, describing the page.
+    User,
+    /// The model.
+    Assistant,
+}
+
+/// A single message in a chat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    /// Who wrote the message.
+    pub role: Role,
+    /// The text of the message.
+    pub content: String,
+}
+
+impl Message {
+    /// Create a new message.
+    #[must_use]
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+/// The number of tokens used by a request, a step, or a whole run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Tokens in the prompt sent to the model.
+    pub prompt_tokens: u32,
+    /// Tokens generated by the model.
+    pub completion_tokens: u32,
+}
+
+impl Usage {
+    /// The total number of tokens used.
+    #[must_use]
+    pub const fn total_tokens(&self) -> u32 {
+        self.prompt_tokens.saturating_add(self.completion_tokens)
+    }
+
+    /// What these tokens cost at the given price, in US dollars.
+    #[must_use]
+    pub fn cost(&self, price: Price) -> f64 {
+        f64::from(self.prompt_tokens).mul_add(
+            price.prompt,
+            f64::from(self.completion_tokens) * price.completion,
+        ) / 1_000_000.0
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens = self.prompt_tokens.saturating_add(other.prompt_tokens);
+        self.completion_tokens = self
+            .completion_tokens
+            .saturating_add(other.completion_tokens);
+    }
+}
+
+/// A message generated by a [`ChatBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// The text of the generated message.
+    pub content: String,
+    /// The tokens used to generate it, if the backend reported them.
+    pub usage: Option<Usage>,
+}
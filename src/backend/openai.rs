@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    retry::{check_status, send_error},
+    ChatBackend, Completion, Message, ModelConfig, Usage,
+};
+
+/// A backend for `OpenAI`, or any server implementing its chat completions API.
+#[derive(Debug, Clone)]
+pub struct OpenAiBackend {
+    /// The HTTP client used to communicate with the API.
+    client: reqwest::Client,
+    /// The base URL of the API.
+    base_url: String,
+    /// The key used to authenticate with the API.
+    api_key: String,
+    /// The model to use.
+    model: String,
+}
+
+impl Default for OpenAiBackend {
+    fn default() -> Self {
+        Self::new("gpt-4")
+    }
+}
+
+impl OpenAiBackend {
+    /// Create a backend for the official `OpenAI` API, using the key in `OPENAI_API_KEY`.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model to use.
+    #[must_use]
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: String::from("https://api.openai.com/v1"),
+            api_key: std::env::var("OPENAI_API_KEY").unwrap_or_default(),
+            model: model.into(),
+        }
+    }
+
+    /// Send requests to a different `OpenAI`-compatible server.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The base URL of the API, like `https://api.openai.com/v1`.
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Use a different API key than the one in `OPENAI_API_KEY`.
+    #[must_use]
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = api_key.into();
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct Response {
+    choices: Vec<Choice>,
+    // Some compatible servers leave this out.
+    usage: Option<ResponseUsage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: Message,
+}
+
+#[derive(Deserialize)]
+struct ResponseUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+#[async_trait]
+impl ChatBackend for OpenAiBackend {
+    async fn complete(&self, messages: &[Message], config: &ModelConfig) -> Result<Completion> {
+        let response = self
+            .client
+            .post(format!(
+                "{}/chat/completions",
+                self.base_url.trim_end_matches('/')
+            ))
+            .bearer_auth(&self.api_key)
+            .json(&Request {
+                model: config.model.as_deref().unwrap_or(&self.model),
+                messages,
+                max_tokens: config.max_tokens,
+                temperature: config.temperature,
+                top_p: config.top_p,
+                seed: config.seed,
+                stop: &config.stop,
+            })
+            .send()
+            .await
+            .map_err(send_error)?;
+
+        let response = check_status(response)
+            .await?
+            .json::<Response>()
+            .await
+            .context("Failed to parse the response from OpenAI.")?;
+
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No choices returned from OpenAI."))?
+            .message;
+
+        Ok(Completion {
+            content: message.content,
+            usage: response.usage.map(|usage| Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            }),
+        })
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+}
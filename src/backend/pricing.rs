@@ -0,0 +1,48 @@
+use serde::Serialize;
+
+/// What a model costs to use, in US dollars per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Price {
+    /// The price of a million tokens in the prompt.
+    pub prompt: f64,
+    /// The price of a million generated tokens.
+    pub completion: f64,
+}
+
+/// Published list prices, matched against the start of the model name.
+///
+/// More specific names come first, so that `gpt-4o-mini` isn't priced as `gpt-4o`, or `gpt-4o` as `gpt-4`.
+const PRICES: &[(&str, Price)] = &[
+    ("gpt-4o-mini", Price::new(0.15, 0.6)),
+    ("gpt-4o", Price::new(2.5, 10.0)),
+    ("gpt-4-turbo", Price::new(10.0, 30.0)),
+    ("gpt-4-32k", Price::new(60.0, 120.0)),
+    ("gpt-4", Price::new(30.0, 60.0)),
+    ("gpt-3.5-turbo", Price::new(0.5, 1.5)),
+    ("claude-3-5-haiku", Price::new(0.8, 4.0)),
+    ("claude-3-5-sonnet", Price::new(3.0, 15.0)),
+    ("claude-3-opus", Price::new(15.0, 75.0)),
+    ("claude-3-sonnet", Price::new(3.0, 15.0)),
+    ("claude-3-haiku", Price::new(0.25, 1.25)),
+];
+
+impl Price {
+    /// Create a price from the cost of a million prompt and completion tokens.
+    #[must_use]
+    pub const fn new(prompt: f64, completion: f64) -> Self {
+        Self { prompt, completion }
+    }
+
+    /// The list price of the given model, or `None` if it isn't known (like most local models).
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The name of the model, like `gpt-4` or `claude-3-5-sonnet-latest`.
+    #[must_use]
+    pub fn of_model(model: &str) -> Option<Self> {
+        PRICES
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, price)| *price)
+    }
+}
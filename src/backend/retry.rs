@@ -0,0 +1,221 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+use super::{ChatBackend, Completion, Message, ModelConfig};
+
+/// An error that may go away if the request is made again, like a rate limit or an overloaded server.
+///
+/// Backends return this (wrapped in an [`anyhow::Error`]) so a [`RetryingBackend`] knows which failures to retry.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct TransientError {
+    /// What went wrong.
+    pub message: String,
+    /// How long the server asked us to wait before trying again, if it did.
+    pub retry_after: Option<Duration>,
+}
+
+/// Decides how often, and how long apart, failed requests are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times to retry a request before giving up.
+    pub max_retries: u32,
+    /// How long to wait before the first retry. The wait doubles after every attempt.
+    pub initial_delay: Duration,
+    /// The longest to wait between two attempts, including waits asked for by the server.
+    pub max_delay: Duration,
+    /// How long a single attempt may take before it's abandoned and retried, if there's a limit.
+    pub request_timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    // `Duration::from_mins` is too new to rely on, since the crate doesn't pin a minimum Rust version.
+    #[allow(clippy::duration_suboptimal_units)]
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            request_timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    #[must_use]
+    pub const fn none() -> Self {
+        Self {
+            max_retries: 0,
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            request_timeout: None,
+        }
+    }
+
+    /// How long to wait before the given retry, starting at 0.
+    ///
+    /// Waits grow exponentially, with up to half of each one picked at random so that clients
+    /// that were rate limited together don't all come back at the same time.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The number of retries made so far.
+    /// * `retry_after` - How long the server asked us to wait, if it did.
+    #[must_use]
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+
+        let delay = self
+            .initial_delay
+            .saturating_mul(2_u32.saturating_pow(retry))
+            .min(self.max_delay);
+
+        delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0..=0.5))
+    }
+}
+
+/// Wraps another backend, retrying requests that fail with a [`TransientError`] or time out.
+#[derive(Debug)]
+pub struct RetryingBackend<B> {
+    /// The backend requests are sent to.
+    inner: B,
+    /// Decides how often, and how long apart, failed requests are retried.
+    policy: RetryPolicy,
+}
+
+impl<B: ChatBackend> RetryingBackend<B> {
+    /// Retry the requests made to the given backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The backend requests are sent to.
+    /// * `policy` - Decides how often, and how long apart, failed requests are retried.
+    #[must_use]
+    pub const fn new(inner: B, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+
+    /// Make a single attempt, turning a timeout into a [`TransientError`].
+    async fn attempt(&self, messages: &[Message], config: &ModelConfig) -> Result<Completion> {
+        let Some(timeout) = self.policy.request_timeout else {
+            return self.inner.complete(messages, config).await;
+        };
+
+        tokio::time::timeout(timeout, self.inner.complete(messages, config))
+            .await
+            .unwrap_or_else(|_| {
+                Err(TransientError {
+                    message: format!(
+                        "The request timed out after {} seconds.",
+                        timeout.as_secs_f64()
+                    ),
+                    retry_after: None,
+                }
+                .into())
+            })
+    }
+}
+
+#[async_trait]
+impl<B: ChatBackend> ChatBackend for RetryingBackend<B> {
+    async fn complete(&self, messages: &[Message], config: &ModelConfig) -> Result<Completion> {
+        let mut retry = 0;
+
+        loop {
+            let error = match self.attempt(messages, config).await {
+                Ok(completion) => return Ok(completion),
+                Err(error) => error,
+            };
+
+            let Some(transient) = error.downcast_ref::<TransientError>() else {
+                return Err(error);
+            };
+            if retry >= self.policy.max_retries {
+                return Err(error);
+            }
+
+            let delay = self.policy.delay(retry, transient.retry_after);
+            warn!(
+                "Request failed ({transient}), retrying in {:.1} seconds.",
+                delay.as_secs_f64()
+            );
+
+            tokio::time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    fn model(&self) -> Option<&str> {
+        self.inner.model()
+    }
+}
+
+/// Turns an error sending a request into a [`TransientError`] if it timed out or couldn't connect.
+pub fn send_error(error: reqwest::Error) -> anyhow::Error {
+    if error.is_timeout() || error.is_connect() {
+        TransientError {
+            message: error.to_string(),
+            retry_after: None,
+        }
+        .into()
+    } else {
+        error.into()
+    }
+}
+
+/// Turns an unsuccessful response into an error, which is a [`TransientError`] for rate limits and server errors.
+///
+/// # Errors
+///
+/// * If the response doesn't have a success status.
+pub async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+
+    let body = response.text().await.unwrap_or_default();
+    let message = format!("The server responded with {status}: {}", body.trim());
+
+    // A 429 also means the account is out of credit, which waiting won't fix.
+    let rate_limited =
+        status == StatusCode::TOO_MANY_REQUESTS && !body.contains("insufficient_quota");
+    if rate_limited || status.is_server_error() {
+        Err(TransientError {
+            message,
+            retry_after,
+        }
+        .into())
+    } else {
+        Err(anyhow::Error::msg(message))
+    }
+}
+
+/// Parses a `Retry-After` header, which is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<f64>() {
+        return (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds));
+    }
+
+    // A date that has already passed means the request can be retried straight away.
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
@@ -1,20 +1,164 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use chromiumoxide::{
-    fetcher::BrowserFetcherRevisionInfo, Browser, BrowserConfig, BrowserFetcher,
-    BrowserFetcherOptions, Page,
+    cdp::browser_protocol::network::{
+        EventLoadingFailed, EventLoadingFinished, EventRequestWillBeSent, RequestId,
+    },
+    handler::viewport::Viewport as DeviceMetrics,
+    Browser, BrowserConfig, Handler, Page,
+};
+#[cfg(feature = "fetcher")]
+use chromiumoxide::{BrowserFetcher, BrowserFetcherOptions};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    env,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, Duration, Instant},
 };
-use std::path::Path;
-use tokio::time::{sleep, Duration};
 use tokio_stream::StreamExt;
 use tracing::debug;
 
+use crate::ScrollDirection;
+
+/// The portion of a page that is currently visible, in CSS pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    /// How far the page has been scrolled down.
+    pub scroll_y: f64,
+    /// The height of the visible area.
+    pub height: f64,
+    /// The height of the whole page.
+    pub page_height: f64,
+}
+
+impl Viewport {
+    /// How many screens of content there are above the visible area.
+    #[must_use]
+    pub fn screens_above(&self) -> f64 {
+        self.scroll_y / self.height
+    }
+
+    /// How many screens of content there are below the visible area.
+    #[must_use]
+    pub fn screens_below(&self) -> f64 {
+        (self.page_height - self.scroll_y - self.height).max(0.0) / self.height
+    }
+}
+
+/// A width and a height, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenSize {
+    /// The width, in pixels.
+    pub width: u32,
+    /// The height, in pixels.
+    pub height: u32,
+}
+
+impl FromStr for ScreenSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.split_once(['x', 'X'])
+            .and_then(|(width, height)| {
+                Some(Self {
+                    width: width.trim().parse().ok()?,
+                    height: height.trim().parse().ok()?,
+                })
+            })
+            .ok_or_else(|| format!("Unknown size \"{s}\", expected WIDTHxHEIGHT, like 1280x720."))
+    }
+}
+
+/// How the browser is launched, and the device its pages pretend to be on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaunchOptions {
+    /// Whether to run the browser without a window. Defaults to `true`.
+    pub headless: bool,
+    /// The size of the browser window, or `None` to let Chrome pick.
+    pub window_size: Option<ScreenSize>,
+    /// The size of the area pages are rendered in, in CSS pixels. Defaults to 800x600.
+    pub viewport: ScreenSize,
+    /// The number of device pixels per CSS pixel, or `None` for the screen's own.
+    pub device_scale_factor: Option<f64>,
+    /// Whether pages see a mobile device with a touch screen, which many sites serve a simpler layout to.
+    pub mobile: bool,
+    /// The language pages see in `navigator.language` and `Accept-Language`, like `en-GB`.
+    pub locale: Option<String>,
+    /// The time zone pages see, like `Europe/Madrid`.
+    ///
+    /// This is set through the `TZ` environment variable of the launched browser, so it has no effect on Windows,
+    /// where Chrome ignores it.
+    pub timezone: Option<String>,
+    /// The user agent sent with every request, instead of Chrome's own.
+    pub user_agent: Option<String>,
+    /// Extra command line arguments for Chrome, like `--disable-gpu`.
+    pub args: Vec<String>,
+}
+
+impl Default for LaunchOptions {
+    fn default() -> Self {
+        Self {
+            headless: true,
+            window_size: None,
+            viewport: ScreenSize {
+                width: 800,
+                height: 600,
+            },
+            device_scale_factor: None,
+            mobile: false,
+            locale: None,
+            timezone: None,
+            user_agent: None,
+            args: Vec::new(),
+        }
+    }
+}
+
+/// The names Chrome and Chromium are installed under, looked up in `PATH`.
+const EXECUTABLE_NAMES: &[&str] = &[
+    "google-chrome",
+    "google-chrome-stable",
+    "chromium",
+    "chromium-browser",
+    "chrome",
+];
+
+/// Where Chrome and Chromium are usually installed, for when they aren't in `PATH`.
+#[cfg(target_os = "linux")]
+const INSTALL_LOCATIONS: &[&str] = &[
+    "/opt/google/chrome/chrome",
+    "/usr/bin/google-chrome",
+    "/usr/bin/chromium",
+    "/usr/bin/chromium-browser",
+    "/snap/bin/chromium",
+];
+#[cfg(target_os = "macos")]
+const INSTALL_LOCATIONS: &[&str] = &[
+    "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+    "/Applications/Chromium.app/Contents/MacOS/Chromium",
+];
+#[cfg(windows)]
+const INSTALL_LOCATIONS: &[&str] = &[
+    r"C:\Program Files\Google\Chrome\Application\chrome.exe",
+    r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe",
+];
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const INSTALL_LOCATIONS: &[&str] = &[];
+
 /// Starts the browser and returns a handle to it.
 ///
 /// # Arguments
 ///
-/// * `browser_path` - The path to the browser executable (will be downloaded if not found).
+/// * `executable` - The path to the browser executable, or `None` to use [`find_chrome`].
+/// * `browser_path` - Where to download Chromium to if no browser is found, with the `fetcher` feature.
 /// * `user_data_dir` - The path to the user data directory (will be created if not found).
-/// * `headless` - Whether to run the browser in headless mode.
+/// * `options` - How to launch the browser, and the device its pages pretend to be on.
 ///
 /// # Errors
 ///
@@ -22,19 +166,105 @@ use tracing::debug;
 /// * If the user data directory cannot be created.
 /// * If the browser cannot be launched.
 /// * If the browser handler cannot be spawned.
-pub async fn init(browser_path: &Path, user_data_dir: &Path, headless: bool) -> Result<Browser> {
-    let browser_info = ensure_browser(browser_path).await?;
+pub async fn init(
+    executable: Option<&Path>,
+    browser_path: &Path,
+    user_data_dir: &Path,
+    options: &LaunchOptions,
+) -> Result<Browser> {
+    let executable = match executable {
+        Some(executable) if executable.is_file() => executable.to_path_buf(),
+        Some(executable) => bail!("There is no browser at {}.", executable.display()),
+        None => resolve_executable(browser_path).await?,
+    };
+    debug!("Using the browser at {}.", executable.display());
 
     let mut config = BrowserConfig::builder()
         .user_data_dir(user_data_dir)
-        .chrome_executable(browser_info.executable_path);
+        .chrome_executable(executable)
+        .viewport(DeviceMetrics {
+            width: options.viewport.width,
+            height: options.viewport.height,
+            device_scale_factor: options.device_scale_factor,
+            emulating_mobile: options.mobile,
+            is_landscape: options.viewport.width > options.viewport.height,
+            has_touch: options.mobile,
+        })
+        .args(&options.args);
 
-    if headless {
+    if !options.headless {
         config = config.with_head();
     }
+    if let Some(size) = options.window_size {
+        config = config.window_size(size.width, size.height);
+    }
+    if let Some(locale) = &options.locale {
+        config = config
+            .arg(format!("--lang={locale}"))
+            .arg(format!("--accept-lang={locale}"));
+    }
+    if let Some(timezone) = &options.timezone {
+        config = config.env("TZ", timezone);
+    }
+    if let Some(user_agent) = &options.user_agent {
+        config = config.arg(format!("--user-agent={user_agent}"));
+    }
 
-    let (browser, mut handler) = Browser::launch(config.build().map_err(|e| anyhow!(e))?).await?;
+    let (browser, handler) = Browser::launch(config.build().map_err(|e| anyhow!(e))?).await?;
+    spawn_handler(handler);
 
+    Ok(browser)
+}
+
+/// Attaches to a browser that is already running, like a Chrome started with `--remote-debugging-port=9222`.
+///
+/// Closing the returned handle would close the browser too, so use [`AgentBuilder::attached_browser`](crate::AgentBuilder::attached_browser)
+/// to leave it running when the agent is done.
+///
+/// # Arguments
+///
+/// * `url` - The browser's `DevTools` WebSocket URL, like `ws://127.0.0.1:9222/devtools/browser/<id>`,
+///   or its HTTP address, like `http://127.0.0.1:9222`, to look the WebSocket URL up.
+///
+/// # Errors
+///
+/// * If the WebSocket URL cannot be looked up.
+/// * If the browser cannot be reached.
+pub async fn connect(url: &str) -> Result<Browser> {
+    let ws_url = if url.starts_with("http://") || url.starts_with("https://") {
+        debugger_url(url).await?
+    } else {
+        url.to_string()
+    };
+
+    let (browser, handler) = Browser::connect(ws_url)
+        .await
+        .with_context(|| format!("Failed to connect to the browser at {url}."))?;
+    spawn_handler(handler);
+
+    Ok(browser)
+}
+
+/// Looks up the `DevTools` WebSocket URL of the browser listening at the given HTTP address.
+async fn debugger_url(url: &str) -> Result<String> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Version {
+        web_socket_debugger_url: String,
+    }
+
+    let version = reqwest::get(format!("{}/json/version", url.trim_end_matches('/')))
+        .await?
+        .error_for_status()?
+        .json::<Version>()
+        .await
+        .with_context(|| format!("Failed to look up the DevTools WebSocket URL at {url}."))?;
+
+    Ok(version.web_socket_debugger_url)
+}
+
+/// Drives the connection to the browser in the background, until it is closed.
+fn spawn_handler(mut handler: Handler) {
     tokio::spawn(async move {
         while let Some(h) = handler.next().await {
             if h.is_err() {
@@ -43,24 +273,364 @@ pub async fn init(browser_path: &Path, user_data_dir: &Path, headless: bool) ->
             }
         }
     });
-
-    Ok(browser)
 }
 
-async fn ensure_browser(path: &Path) -> Result<BrowserFetcherRevisionInfo> {
-    let fetcher = BrowserFetcher::new(BrowserFetcherOptions::builder().with_path(path).build()?);
+/// Finds a Chrome or Chromium installed on this machine.
+///
+/// Looks at the `CHROME` environment variable, then for the usual executable names in `PATH`,
+/// then in the usual install locations, and returns the first browser found.
+#[must_use]
+pub fn find_chrome() -> Option<PathBuf> {
+    let locations: Vec<&Path> = INSTALL_LOCATIONS.iter().map(Path::new).collect();
 
-    Ok(fetcher.fetch().await?)
+    find_chrome_in(
+        env::var_os("CHROME").as_deref().map(Path::new),
+        env::var_os("PATH").as_deref(),
+        &locations,
+    )
 }
 
-/// Waits for the page to navigate or for 5 seconds to pass.
+/// Finds a Chrome or Chromium in the given places, the way [`find_chrome`] does in the real ones.
 ///
 /// # Arguments
 ///
-/// * `page` - The page to wait for.
-pub async fn wait_for_page(page: &Page) {
-    tokio::select! {
-        _ = page.wait_for_navigation() => {},
-        _ = sleep(Duration::from_secs(5)) => {},
+/// * `chrome` - The value of the `CHROME` environment variable, if it's set.
+/// * `path` - The value of the `PATH` environment variable, if it's set.
+/// * `locations` - Where the browser is usually installed.
+#[must_use]
+pub fn find_chrome_in(
+    chrome: Option<&Path>,
+    path: Option<&OsStr>,
+    locations: &[&Path],
+) -> Option<PathBuf> {
+    let from_path = path.into_iter().flat_map(|paths| {
+        env::split_paths(paths)
+            .flat_map(|dir| {
+                EXECUTABLE_NAMES
+                    .iter()
+                    .map(move |name| dir.join(name).with_extension(env::consts::EXE_EXTENSION))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    chrome
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(from_path)
+        .chain(locations.iter().map(|location| location.to_path_buf()))
+        .find(|path| path.is_file())
+}
+
+/// Finds an installed browser, or downloads Chromium if the `fetcher` feature is enabled.
+#[cfg(feature = "fetcher")]
+async fn resolve_executable(browser_path: &Path) -> Result<PathBuf> {
+    if let Some(executable) = find_chrome() {
+        return Ok(executable);
+    }
+
+    debug!(
+        "No browser found, downloading Chromium to {}.",
+        browser_path.display()
+    );
+    let fetcher = BrowserFetcher::new(
+        BrowserFetcherOptions::builder()
+            .with_path(browser_path)
+            .build()?,
+    );
+
+    Ok(fetcher.fetch().await?.executable_path)
+}
+
+/// Finds an installed browser.
+#[cfg(not(feature = "fetcher"))]
+#[allow(clippy::unused_async)]
+async fn resolve_executable(_browser_path: &Path) -> Result<PathBuf> {
+    find_chrome().ok_or_else(|| {
+        anyhow!("No Chrome or Chromium found. Install one, point CHROME or --chrome-path at it, or build with the `fetcher` feature to download Chromium.")
+    })
+}
+
+/// Decides when a page has settled after an action, and is ready to be described to the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SettleOptions {
+    /// The longest to wait for the page to settle. Defaults to 10 seconds.
+    pub max_wait: Duration,
+    /// How long the network and the DOM must stay quiet for. Defaults to 500 milliseconds.
+    pub idle_time: Duration,
+    /// How many requests may still be in flight on an idle network, for long polls and the like. Defaults to 2.
+    pub max_in_flight: usize,
+}
+
+impl Default for SettleOptions {
+    fn default() -> Self {
+        Self {
+            max_wait: Duration::from_secs(10),
+            idle_time: Duration::from_millis(500),
+            max_in_flight: 2,
+        }
+    }
+}
+
+/// Why [`Settler::wait`] stopped waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Settled {
+    /// The network was idle and the DOM stopped changing.
+    Quiet,
+    /// The page was still busy when the maximum wait ran out.
+    MaxWaitReached {
+        /// The number of requests still in flight.
+        in_flight: usize,
+        /// Whether the DOM was still changing.
+        dom_changing: bool,
+    },
+}
+
+/// The requests a page has in flight.
+#[derive(Debug)]
+struct Network {
+    /// The requests that have been sent but haven't finished or failed.
+    in_flight: HashSet<RequestId>,
+    /// When the number of requests in flight last dropped to the limit, or `None` if it's above it.
+    idle_since: Option<Instant>,
+}
+
+/// Installs a `MutationObserver` in the page (once per document), and reports how long ago the DOM last changed.
+///
+/// Changes to the agent's own attributes are ignored, so describing a page doesn't keep it busy.
+const DOM_QUIET_SCRIPT: &str = r"(() => {
+    if (window.__agentLastMutation === undefined) {
+        window.__agentLastMutation = performance.now();
+        new MutationObserver((records) => {
+            if (records.some((r) => r.type !== 'attributes' || !r.attributeName.startsWith('data-agent-'))) {
+                window.__agentLastMutation = performance.now();
+            }
+        }).observe(document, { subtree: true, childList: true, attributes: true, characterData: true });
+    }
+    return { quietFor: performance.now() - window.__agentLastMutation, readyState: document.readyState };
+})()";
+
+/// How long to wait between two checks of the DOM.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits for a page to settle after an action, by watching its network requests and its DOM.
+///
+/// Requests are tracked in the background from the moment the settler is started, so it should
+/// be started along with the page, not when it's time to wait.
+#[derive(Debug)]
+pub struct Settler {
+    /// The page being watched.
+    page: Page,
+    /// Decides when the page has settled.
+    options: SettleOptions,
+    /// The requests the page has in flight, updated by `task`.
+    network: Arc<Mutex<Network>>,
+    /// Follows the page's network events.
+    task: JoinHandle<()>,
+}
+
+impl Settler {
+    /// Start tracking the network requests made by a page.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - The page to watch.
+    /// * `options` - Decides when the page has settled.
+    ///
+    /// # Errors
+    ///
+    /// * If the page's network events cannot be listened to.
+    pub async fn start(page: &Page, options: SettleOptions) -> Result<Self> {
+        let network = Arc::new(Mutex::new(Network {
+            in_flight: HashSet::new(),
+            idle_since: Some(Instant::now()),
+        }));
+
+        let sent = page.event_listener::<EventRequestWillBeSent>().await?;
+        let finished = page.event_listener::<EventLoadingFinished>().await?;
+        let failed = page.event_listener::<EventLoadingFailed>().await?;
+        let mut events = sent
+            .map(|event| (event.request_id.clone(), true))
+            .merge(finished.map(|event| (event.request_id.clone(), false)))
+            .merge(failed.map(|event| (event.request_id.clone(), false)));
+
+        let task = tokio::spawn({
+            let network = Arc::clone(&network);
+            async move {
+                while let Some((request_id, started)) = events.next().await {
+                    let mut network = network.lock().unwrap_or_else(PoisonError::into_inner);
+                    if started {
+                        network.in_flight.insert(request_id);
+                    } else {
+                        network.in_flight.remove(&request_id);
+                    }
+
+                    if network.in_flight.len() > options.max_in_flight {
+                        network.idle_since = None;
+                    } else if network.idle_since.is_none() {
+                        network.idle_since = Some(Instant::now());
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            page: page.clone(),
+            options,
+            network,
+            task,
+        })
+    }
+
+    /// Waits for the network to be idle and the DOM to stop changing, or for the maximum wait to run out.
+    ///
+    /// Always waits for at least the idle time, so that an action has the chance to start a navigation or a request.
+    pub async fn wait(&self) -> Settled {
+        let started = Instant::now();
+        let mut dom_quiet = false;
+
+        let quiet = tokio::time::timeout(self.options.max_wait, async {
+            loop {
+                dom_quiet = self.dom_quiet().await;
+                if dom_quiet && self.network_idle(started) {
+                    return;
+                }
+                sleep(POLL_INTERVAL).await;
+            }
+        })
+        .await;
+
+        let waited = started.elapsed().as_secs_f64();
+        if quiet.is_ok() {
+            debug!("The page settled after {waited:.1} seconds: the network is idle and the DOM is quiet.");
+            return Settled::Quiet;
+        }
+
+        let in_flight = self.in_flight();
+        debug!(
+            "Stopped waiting for the page after {waited:.1} seconds, with {in_flight} requests in flight and the DOM {}.",
+            if dom_quiet { "quiet" } else { "still changing" }
+        );
+        Settled::MaxWaitReached {
+            in_flight,
+            dom_changing: !dom_quiet,
+        }
+    }
+
+    /// The number of requests the page has in flight.
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.network
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .in_flight
+            .len()
+    }
+
+    /// Whether no more than the allowed requests have been in flight for the idle time, since the given instant.
+    fn network_idle(&self, since: Instant) -> bool {
+        self.network
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .idle_since
+            .is_some_and(|idle_since| idle_since.max(since).elapsed() >= self.options.idle_time)
+    }
+
+    /// Whether the document has been parsed, and hasn't changed for the idle time.
+    async fn dom_quiet(&self) -> bool {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DomState {
+            quiet_for: f64,
+            ready_state: String,
+        }
+
+        // Fails while the page navigates, and the old document is replaced.
+        let Ok(result) = self.page.evaluate_expression(DOM_QUIET_SCRIPT).await else {
+            return false;
+        };
+
+        result.into_value::<DomState>().is_ok_and(|state| {
+            state.ready_state != "loading"
+                && state.quiet_for >= self.options.idle_time.as_secs_f64() * 1000.0
+        })
     }
 }
+
+impl Drop for Settler {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Returns the currently visible portion of the page.
+///
+/// # Arguments
+///
+/// * `page` - The page to measure.
+///
+/// # Errors
+///
+/// * If the layout metrics cannot be retrieved.
+pub async fn viewport(page: &Page) -> Result<Viewport> {
+    let metrics = page.layout_metrics().await?;
+
+    Ok(Viewport {
+        scroll_y: metrics.css_visual_viewport.page_y,
+        height: metrics.css_visual_viewport.client_height,
+        page_height: metrics.css_content_size.height,
+    })
+}
+
+/// Scrolls the page by most of a screen in the given direction.
+///
+/// # Arguments
+///
+/// * `page` - The page to scroll.
+/// * `direction` - The direction to scroll in.
+///
+/// # Errors
+///
+/// * If the scroll script cannot be evaluated.
+pub async fn scroll(page: &Page, direction: ScrollDirection) -> Result<()> {
+    let sign = match direction {
+        ScrollDirection::Up => "-",
+        ScrollDirection::Down => "",
+    };
+
+    page.evaluate(format!(
+        "window.scrollBy(0, {sign}window.innerHeight * 0.8)"
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Navigates back to the previous page in the history.
+///
+/// # Arguments
+///
+/// * `page` - The page to navigate.
+///
+/// # Errors
+///
+/// * If the navigation script cannot be evaluated.
+pub async fn go_back(page: &Page) -> Result<()> {
+    page.evaluate("history.back()").await?;
+
+    Ok(())
+}
+
+/// Navigates forward to the next page in the history.
+///
+/// # Arguments
+///
+/// * `page` - The page to navigate.
+///
+/// # Errors
+///
+/// * If the navigation script cannot be evaluated.
+pub async fn go_forward(page: &Page) -> Result<()> {
+    page.evaluate("history.forward()").await?;
+
+    Ok(())
+}
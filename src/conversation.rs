@@ -0,0 +1,359 @@
+use anyhow::Result;
+use indoc::formatdoc;
+use tracing::{debug, info};
+
+use crate::{
+    backend::{ChatBackend, Message, ModelConfig, OpenAiBackend, Role, Usage},
+    history::PAGE_CONTENT_MARKER,
+    Action, ActionError, HistoryPolicy, Protocol,
+};
+
+/// A conversation with a language model (GPT-4, unless another backend is given).
+#[derive(Debug)]
+pub struct Conversation {
+    /// The goal for the agent to achieve.
+    goal: String,
+    /// Whether the goal was given by the user, and cannot be changed by the agent.
+    goal_locked: bool,
+    /// The progress the agent reported towards a goal given by the user, oldest first.
+    progress: Vec<String>,
+    /// The format GPT-4 is asked to reply in.
+    protocol: Protocol,
+    /// The backend used to communicate with the model.
+    backend: Box<dyn ChatBackend>,
+    /// The model, and the sampling settings, used for every request.
+    model_config: ModelConfig,
+    /// The instructions sent at the start of every request.
+    system_prompt: Message,
+    /// The messages exchanged with the model so far, oldest first.
+    history: Vec<Message>,
+    /// Decides which earlier messages are sent with each request.
+    history_policy: HistoryPolicy,
+    /// A summary of the steps that were dropped from the history, if any.
+    summary: Option<String>,
+    /// The tokens used by every request so far.
+    usage: Usage,
+    /// The tokens used by each call to [`Conversation::request_action`], including any summaries it asked for.
+    step_usage: Vec<Usage>,
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self {
+            goal: String::from("Visit 10 webpages."),
+            goal_locked: false,
+            progress: Vec::new(),
+            protocol: Protocol::default(),
+            backend: Box::new(OpenAiBackend::default()),
+            model_config: ModelConfig::default(),
+            system_prompt: system_prompt(false, Protocol::default()),
+            history: Vec::new(),
+            history_policy: HistoryPolicy::default(),
+            summary: None,
+            usage: Usage::default(),
+            step_usage: Vec::new(),
+        }
+    }
+}
+
+impl Conversation {
+    /// Create a new conversation with GPT-4.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new conversation with GPT-4, working towards a fixed goal.
+    ///
+    /// Unlike [`Conversation::new`], the agent cannot replace this goal: a `GOAL` command reports its progress instead, and
+    /// a `DONE` command reports the result once the goal is achieved.
+    ///
+    /// # Arguments
+    ///
+    /// * `goal` - The goal for the agent to achieve.
+    #[must_use]
+    pub fn with_goal(goal: impl Into<String>) -> Self {
+        Self {
+            goal: goal.into(),
+            goal_locked: true,
+            system_prompt: system_prompt(true, Protocol::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Use a different backend to talk to the model.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend to send requests to.
+    #[must_use]
+    pub fn with_backend(mut self, backend: impl ChatBackend + 'static) -> Self {
+        self.backend = Box::new(backend);
+        self
+    }
+
+    /// Set the model, and the sampling settings, used for every request.
+    #[must_use]
+    pub fn with_model_config(mut self, model_config: ModelConfig) -> Self {
+        self.model_config = model_config;
+        self
+    }
+
+    /// Set the format GPT-4 is asked to reply in.
+    ///
+    /// Replies are accepted in either format, so this only changes the instructions given to the model.
+    #[must_use]
+    pub fn with_protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self.system_prompt = system_prompt(self.goal_locked, protocol);
+        self
+    }
+
+    /// Set the policy that decides which earlier messages are sent with each request.
+    #[must_use]
+    pub const fn with_history_policy(mut self, history_policy: HistoryPolicy) -> Self {
+        self.history_policy = history_policy;
+        self
+    }
+
+    /// The goal the agent is currently working towards.
+    #[must_use]
+    pub fn goal(&self) -> &str {
+        &self.goal
+    }
+
+    /// Whether the goal was given by the user, and cannot be changed by the agent.
+    #[must_use]
+    pub const fn is_goal_locked(&self) -> bool {
+        self.goal_locked
+    }
+
+    /// The progress the agent reported towards a goal given by the user, oldest first.
+    #[must_use]
+    pub fn progress(&self) -> &[String] {
+        &self.progress
+    }
+
+    /// A summary of the steps that no longer fit in the history, if summaries are enabled.
+    #[must_use]
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    /// The tokens used by every request so far, as reported by the backend.
+    #[must_use]
+    pub const fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// The tokens used by each step so far, oldest first. A step is one call to [`Conversation::request_action`].
+    #[must_use]
+    pub fn step_usage(&self) -> &[Usage] {
+        &self.step_usage
+    }
+
+    /// The model, and the sampling settings, used for every request.
+    #[must_use]
+    pub const fn model_config(&self) -> &ModelConfig {
+        &self.model_config
+    }
+
+    /// The name of the model requests are sent to, if it's known.
+    #[must_use]
+    pub fn model(&self) -> Option<&str> {
+        self.model_config
+            .model
+            .as_deref()
+            .or_else(|| self.backend.model())
+    }
+
+    /// Request and execute an action from GPT-4.
+    #[tracing::instrument]
+    pub async fn request_action(&mut self, url: &str, page_content: &str) -> Result<Action> {
+        self.step_usage.push(Usage::default());
+        self.history.push(Message::new(
+            Role::User,
+            format!(
+                "OBJECTIVE: {}\nCURRENT URL: {url}{PAGE_CONTENT_MARKER}{page_content}",
+                self.goal
+            ),
+        ));
+        self.enforce_context_length().await?;
+
+        let completion = self
+            .backend
+            .complete(&self.messages(), &self.model_config)
+            .await?;
+        self.record_usage(completion.usage);
+
+        self.history
+            .push(Message::new(Role::Assistant, completion.content.clone()));
+
+        let action = Action::parse(&completion.content).map_err(ActionError::from)?;
+
+        if let Action::Goal(goal) = &action {
+            if self.goal_locked {
+                info!("Agent reported its progress: \"{goal}\".");
+                self.progress.push(goal.clone());
+            } else {
+                debug!("Agent updated its goal to \"{goal}\".");
+                self.goal.clone_from(goal);
+            }
+        }
+
+        Ok(action)
+    }
+
+    /// Tell GPT-4 that its last action failed, so it can pick a different one on the next request.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The reason the action failed.
+    pub fn report_error(&mut self, error: &ActionError) {
+        self.history.push(Message::new(
+            Role::User,
+            format!("ERROR: {error}. Respond with a different command."),
+        ));
+    }
+
+    /// The messages sent with the next request: the system prompt, the summary (if any), and the history.
+    fn messages(&self) -> Vec<Message> {
+        let summary = self.summary.as_ref().map(|summary| {
+            Message::new(Role::User, format!("SUMMARY OF EARLIER STEPS: {summary}"))
+        });
+
+        std::iter::once(self.system_prompt.clone())
+            .chain(summary)
+            .chain(self.history.iter().cloned())
+            .collect()
+    }
+
+    fn record_usage(&mut self, usage: Option<Usage>) {
+        if let Some(usage) = usage {
+            debug!("Got a response, used {} tokens.", usage.total_tokens());
+            self.usage += usage;
+            if let Some(step) = self.step_usage.last_mut() {
+                *step += usage;
+            }
+        } else {
+            debug!("Got a response, but the backend didn't report its usage.");
+        }
+    }
+
+    async fn enforce_context_length(&mut self) -> Result<()> {
+        let evicted = self.history_policy.apply(&mut self.history);
+        if evicted.is_empty() {
+            return Ok(());
+        }
+        debug!("Dropped {} messages from the history.", evicted.len());
+
+        if self.history_policy.summarize {
+            self.summarize(&evicted).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Fold the given messages into the running summary of earlier steps.
+    async fn summarize(&mut self, evicted: &[Message]) -> Result<()> {
+        let steps = evicted
+            .iter()
+            .map(|message| match message.role {
+                Role::Assistant => format!("AGENT: {}", message.content),
+                _ => message.content.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let request = [
+            Message::new(Role::System, SUMMARY_PROMPT),
+            Message::new(
+                Role::User,
+                format!(
+                    "OBJECTIVE: {}\nPREVIOUS SUMMARY: {}\n\nSTEPS:\n{steps}",
+                    self.goal,
+                    self.summary.as_deref().unwrap_or("None.")
+                ),
+            ),
+        ];
+
+        let completion = self.backend.complete(&request, &self.model_config).await?;
+        self.record_usage(completion.usage);
+
+        debug!(
+            "Updated the summary of earlier steps: {}",
+            completion.content
+        );
+        self.summary = Some(completion.content);
+        Ok(())
+    }
+}
+
+/// The instructions for summarising steps that were dropped from the history.
+const SUMMARY_PROMPT: &str = "You summarise the progress of an agent controlling a browser. You are given the previous summary and the steps taken since. Reply with an updated summary of at most a few sentences, keeping the pages visited, the actions taken, and anything learned that helps with the objective.";
+
+fn system_prompt(goal_locked: bool, protocol: Protocol) -> Message {
+    let (goal_instructions, goal_command, done_command) = if goal_locked {
+        (
+            "You are given an objective by the user, which you cannot change. Take whichever actions are needed to achieve it.",
+            "Reports your progress towards the objective, and carries on.",
+            "Reports the result once the objective is complete, and ends your session.",
+        )
+    } else {
+        (
+            "You are not given a goal but should create and alter a goal based on the previous actions you have taken. Your initial goal should be to visit at least 10 webpages and update your goal based on the content of those page.",
+            "Outputs your updated goal.",
+            "Reports what you found, and ends your session.",
+        )
+    };
+
+    let commands = match protocol {
+        Protocol::Text => formatdoc!("
+            You must respond with ONLY one of the following commands AND NOTHING ELSE:
+                - CLICK X - click on a given element. You can only click on links, buttons, and inputs!
+                - TYPE X \"TEXT\" - type the specified text into the input with id X and press ENTER
+                - SCROLL UP|DOWN - scroll the page up or down by one screen
+                - SCROLL X - scroll the element with id X into view
+                - GOTO URL - navigate to the given URL
+                - BACK - go back to the previous page
+                - FORWARD - go forward to the next page
+                - RELOAD - reload the current page
+                - SELECT X \"OPTION\" - choose the option with the given text in the dropdown with id X
+                - CHECK X / UNCHECK X - check or uncheck the checkbox or radio button with id X
+                - CLEAR X - clear the text in the input with id X
+                - SWITCHTAB N - act on the tab with number N instead
+                - CLOSETAB N - close the tab with number N
+                - NEWTAB URL - open the given URL in a new tab, and act on it
+                - GOAL \"TEXT\" - {goal_command}
+                - DONE \"TEXT\" - {done_command}
+        "),
+        Protocol::Json => formatdoc!("
+            You must respond with ONLY a JSON object matching the following JSON schema AND NOTHING ELSE:
+            {schema}
+
+            The \"goal\" action: {goal_command}
+            The \"done\" action: {done_command}
+        ", schema = Action::json_schema()),
+    };
+
+    Message::new(
+        Role::System,
+        formatdoc!("
+            You are an agent controlling a browser. You are given the URL of the current website, and a simplified markup description of the page contents, which looks like this:
+            <p id=0>text</p>
+            <link id=1 href=\"link url\">text</link>
+            <button id=2>text</button>
+            <input id=3>placeholder</input>
+            <img id=4 alt=\"image description\"/>
+            <input id=5 type=checkbox checked=false>label</input>
+            <select id=6 value=\"selected option\" options=[\"option\", ...]>label</select>
+            <textarea id=7 value=\"current text\">label</textarea>
+            Elements marked `hidden` are not visible, and elements marked `obscured` are covered by something else, like a cookie banner. Actions on them will probably fail.
+            When more than one tab is open, the description starts with a numbered list of the tabs, and the one you are acting on is marked `current`. Links and buttons can open new tabs, which you are switched to.
+
+            {goal_instructions}
+
+            {commands}
+        "),
+    )
+}
@@ -0,0 +1,90 @@
+use crate::{
+    backend::{Message, Role},
+    count_tokens,
+};
+
+/// The text that separates the page description from the rest of a user message.
+pub const PAGE_CONTENT_MARKER: &str = "\nPAGE CONTENT: ";
+
+/// What replaces the description of pages that are no longer kept in full.
+const LEFT_OUT: &str = "[left out, this page is no longer shown]";
+
+/// The rough number of tokens every message costs on top of its content.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Decides which earlier messages are sent to the model with each request.
+///
+/// Page descriptions are by far the largest messages, so only the most recent ones are kept in full.
+/// The model's actions (and any errors) are kept until the history outgrows its token budget, at which
+/// point the oldest steps are dropped, and optionally summarised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryPolicy {
+    /// The maximum number of tokens the earlier steps may use, not counting the system prompt or the latest message.
+    pub max_tokens: usize,
+    /// How many of the most recent page descriptions to keep in full. The current page is always kept, so 0 counts as 1.
+    pub full_pages: usize,
+    /// Whether to ask the model for a running summary of the steps that are dropped.
+    pub summarize: bool,
+}
+
+impl Default for HistoryPolicy {
+    fn default() -> Self {
+        Self {
+            max_tokens: 3000,
+            full_pages: 1,
+            summarize: false,
+        }
+    }
+}
+
+impl HistoryPolicy {
+    /// Shortens old page descriptions, then drops the oldest steps until the earlier steps fit in the budget.
+    ///
+    /// The latest message is always kept, and doesn't count towards the budget, so a large page doesn't
+    /// push out the steps that led to it. Returns the messages that were dropped, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The messages after the system prompt, oldest first.
+    pub fn apply(&self, history: &mut Vec<Message>) -> Vec<Message> {
+        let pages: Vec<usize> = history
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| is_page(message))
+            .map(|(i, _)| i)
+            .collect();
+
+        for &i in pages.iter().rev().skip(self.full_pages.max(1)) {
+            if let Some((head, _)) = history[i].content.split_once(PAGE_CONTENT_MARKER) {
+                history[i].content = format!("{head}{PAGE_CONTENT_MARKER}{LEFT_OUT}");
+            }
+        }
+
+        let mut evicted = Vec::new();
+        while history.len() > 1 && tokens(&history[..history.len() - 1]) > self.max_tokens {
+            // Drop a whole step at a time: the page, the model's reply, and any errors that followed.
+            let step = history
+                .iter()
+                .skip(1)
+                .position(is_page)
+                .map_or(history.len() - 1, |i| i + 1);
+
+            evicted.extend(history.drain(..step));
+        }
+
+        evicted
+    }
+}
+
+/// Whether the message describes a page, rather than reporting an error.
+fn is_page(message: &Message) -> bool {
+    message.role == Role::User && message.content.contains(PAGE_CONTENT_MARKER)
+}
+
+/// The rough number of tokens the given messages use.
+fn tokens(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| count_tokens(&message.content) + TOKENS_PER_MESSAGE)
+        .sum()
+}
@@ -1,76 +1,445 @@
-use anyhow::{anyhow, Context, Result};
-use chromiumoxide::Element;
+use std::str::FromStr;
 
-/// Translates the given elements into a format GPT-4 can understand.
+use crate::{browser::Viewport, count_tokens, Bounds, DomSnapshot, SnapshotElement};
+
+/// The elements described by [`translate`].
+pub const SELECTOR: &str = "p, button, input, select, textarea, a, img";
+
+/// The tokens set aside for the note saying that elements were left out.
+const OMISSION_NOTE_TOKENS: usize = 20;
+
+/// How far outside the viewport (as a fraction of its height) elements are still included.
+const VIEWPORT_MARGIN: f64 = 0.5;
+
+/// Where the description of a page comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Translator {
+    /// A fixed set of HTML elements, see [`translate`].
+    #[default]
+    Dom,
+    /// The actionable nodes of the accessibility tree, see [`translate_accessibility`](crate::translate_accessibility).
+    Accessibility,
+}
+
+impl FromStr for Translator {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dom" => Ok(Self::Dom),
+            "accessibility" | "a11y" => Ok(Self::Accessibility),
+            _ => Err(format!(
+                "Unknown translator \"{s}\", expected \"dom\" or \"accessibility\"."
+            )),
+        }
+    }
+}
+
+/// What to do with elements that are hidden, or covered by other elements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VisibilityFilter {
+    /// Include every element, as if it were visible.
+    #[default]
+    Off,
+    /// Include every element, marking the ones that are `hidden` or `obscured`.
+    Mark,
+    /// Leave out elements that are hidden or obscured.
+    Drop,
+}
+
+impl FromStr for VisibilityFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "mark" => Ok(Self::Mark),
+            "drop" => Ok(Self::Drop),
+            _ => Err(format!(
+                "Unknown visibility filter \"{s}\", expected \"off\", \"mark\" or \"drop\"."
+            )),
+        }
+    }
+}
+
+/// Options that control how a page is translated.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TranslateOptions {
+    /// Where the description of the page comes from.
+    pub translator: Translator,
+    /// Whether to include paragraphs in the translation.
+    pub include_paragraphs: bool,
+    /// Whether to only include elements inside (or near) the viewport.
+    pub viewport_only: bool,
+    /// What to do with elements that are hidden, or covered by other elements.
+    pub visibility: VisibilityFilter,
+    /// The maximum number of tokens the translation may use, if any.
+    ///
+    /// When the page doesn't fit, the least important elements are left out, see [`Priority`].
+    pub max_tokens: Option<usize>,
+}
+
+/// How important an element is to the model, from most to least important.
 ///
-/// # Arguments
+/// When a page doesn't fit in the token budget, elements are left out starting from the bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Buttons and form controls, which the model needs to make progress.
+    Control,
+    /// Links to other pages.
+    Link,
+    /// Headings, which describe the structure of the page.
+    Heading,
+    /// Images, described by their alt text.
+    Image,
+    /// Paragraphs and other text.
+    Text,
+}
+
+impl Priority {
+    /// The priority of an element with the given tag name.
+    #[must_use]
+    pub fn of_tag(tag: &str) -> Self {
+        match tag {
+            "A" => Self::Link,
+            "IMG" => Self::Image,
+            "P" => Self::Text,
+            tag if tag.len() == 2 && tag.starts_with('H') => Self::Heading,
+            _ => Self::Control,
+        }
+    }
+
+    /// The priority of an accessibility node with the given role.
+    #[must_use]
+    pub fn of_role(role: &str) -> Self {
+        match role {
+            "link" => Self::Link,
+            "heading" => Self::Heading,
+            "img" | "image" => Self::Image,
+            "StaticText" | "paragraph" => Self::Text,
+            _ => Self::Control,
+        }
+    }
+}
+
+/// Leaves out the least important lines of a translation until it fits in the given number of tokens.
+///
+/// Lines that don't fit are skipped, but smaller, less important lines after them can still be kept.
+/// The remaining lines keep their order, and a note telling the model how many were left out is added.
+#[must_use]
+pub fn fit_to_budget(lines: Vec<(Priority, String)>, max_tokens: Option<usize>) -> Vec<String> {
+    // Each line also costs roughly one token for the newline that separates it from the next.
+    let costs: Vec<usize> = lines
+        .iter()
+        .map(|(_, line)| count_tokens(line) + 1)
+        .collect();
+
+    let Some(max_tokens) = max_tokens.filter(|max| costs.iter().sum::<usize>() > *max) else {
+        return lines.into_iter().map(|(_, line)| line).collect();
+    };
+
+    let mut order: Vec<usize> = (0..lines.len()).collect();
+    order.sort_by_key(|&i| lines[i].0);
+
+    let budget = max_tokens.saturating_sub(OMISSION_NOTE_TOKENS);
+    let mut kept = vec![false; lines.len()];
+    let mut used = 0;
+    for i in order {
+        if used + costs[i] > budget {
+            continue;
+        }
+
+        used += costs[i];
+        kept[i] = true;
+    }
+
+    let omitted = kept.iter().filter(|kept| !**kept).count();
+    let mut summary: Vec<String> = lines
+        .into_iter()
+        .zip(kept)
+        .filter_map(|((_, line), kept)| kept.then_some(line))
+        .collect();
+    summary.push(format!(
+        "[{omitted} less important elements were left out to keep the page description short]"
+    ));
+
+    summary
+}
+
+/// Applies the visibility filter to an element.
+///
+/// Returns `None` if the element should be left out, or the marker to add after its id otherwise.
+#[must_use]
+pub fn visibility_marker(
+    element: &SnapshotElement,
+    filter: VisibilityFilter,
+) -> Option<&'static str> {
+    let marker = if !element.visible {
+        " hidden"
+    } else if element.obscured {
+        " obscured"
+    } else {
+        ""
+    };
+
+    match filter {
+        VisibilityFilter::Off => Some(""),
+        VisibilityFilter::Mark => Some(marker),
+        VisibilityFilter::Drop => marker.is_empty().then_some(""),
+    }
+}
+
+/// Where an element sits relative to the viewport.
+pub enum Placement {
+    /// Too far above the viewport to be included.
+    Above,
+    /// Inside (or near) the viewport.
+    Near,
+    /// Too far below the viewport to be included.
+    Below,
+}
+
+/// Finds where an element with the given bounds sits relative to the viewport.
+///
+/// Elements without bounds aren't rendered, so there's nothing to scroll to and `None` is returned.
+pub fn placement(bounds: Option<&Bounds>, viewport: &Viewport) -> Option<Placement> {
+    let bounds = bounds?;
+
+    let margin = viewport.height * VIEWPORT_MARGIN;
+    if bounds.y + bounds.height < -margin {
+        Some(Placement::Above)
+    } else if bounds.y > viewport.height + margin {
+        Some(Placement::Below)
+    } else {
+        Some(Placement::Near)
+    }
+}
+
+/// Adds markers telling the model how many elements were left out above and below the viewport.
 ///
-/// * `elements` - The elements to translate.
-/// * `should_include_p` - Whether to include paragraphs in the translation.
+/// The markers don't name a command, since the command to scroll depends on the protocol the model replies in.
+pub fn add_hidden_markers(
+    summary: &mut Vec<String>,
+    viewport: &Viewport,
+    hidden_above: usize,
+    hidden_below: usize,
+) {
+    if hidden_above > 0 {
+        summary.insert(
+            0,
+            format!(
+                "[{hidden_above} more elements in the {:.1} screens above, scroll up to see them]",
+                viewport.screens_above()
+            ),
+        );
+    }
+    if hidden_below > 0 {
+        summary.push(format!(
+            "[{hidden_below} more elements in the {:.1} screens below, scroll down to see them]",
+            viewport.screens_below()
+        ));
+    }
+}
+
+/// Translates the given snapshot into a format GPT-4 can understand.
 ///
-/// # Errors
+/// # Arguments
 ///
-/// * If the elements cannot be translated.
-pub async fn translate(elements: &[Element], should_include_p: bool) -> Result<String> {
+/// * `snapshot` - The elements to translate, usually matching [`SELECTOR`].
+/// * `viewport` - The currently visible portion of the page.
+/// * `options` - Options that control which elements are included.
+#[must_use]
+pub fn translate(
+    snapshot: &DomSnapshot,
+    viewport: &Viewport,
+    options: &TranslateOptions,
+) -> String {
     let mut summary = Vec::new();
+    let (mut hidden_above, mut hidden_below) = (0, 0);
+
+    for element in &snapshot.elements {
+        let priority = Priority::of_tag(&element.tag);
+
+        if options.viewport_only {
+            match placement(element.bounds.as_ref(), viewport) {
+                Some(Placement::Near) => {}
+                Some(Placement::Above) => {
+                    hidden_above += 1;
+                    continue;
+                }
+                Some(Placement::Below) => {
+                    hidden_below += 1;
+                    continue;
+                }
+                None => continue,
+            }
+        }
+
+        let Some(marker) = visibility_marker(element, options.visibility) else {
+            continue;
+        };
+
+        // Markers go right after the id, like `id=3 obscured`.
+        let i = format!("{}{marker}", element.id);
+        let inner_text = element.text.as_deref();
 
-    for (i, element) in elements.iter().enumerate() {
-        let inner_text = element.inner_text().await?;
-
-        match element
-            .property("tagName")
-            .await
-            .context("Failed to get tag name")?
-            .ok_or_else(|| anyhow!("Failed to get tag name"))?
-            .as_str()
-            .context("Failed to get tag name")?
-        {
+        match element.tag.as_str() {
             "BUTTON" => {
                 let Some(inner_text) = inner_text else {
-                    continue
+                    continue;
                 };
 
-                summary.push(format!("<button id={i}>{inner_text}</button>"));
+                summary.push((priority, format!("<button id={i}>{inner_text}</button>")));
             }
             "P" => {
-                if !should_include_p {
+                if !options.include_paragraphs {
                     continue;
                 }
 
                 let Some(inner_text) = inner_text else {
-                    continue
+                    continue;
                 };
 
-                summary.push(format!("<p id={i}>{inner_text}</p>"));
+                summary.push((priority, format!("<p id={i}>{inner_text}</p>")));
             }
             "IMG" => {
-                let Some(alt_text) = element.attribute("alt").await? else {
-                    continue
+                let Some(alt_text) = element.attribute("alt") else {
+                    continue;
                 };
 
-                summary.push(format!("<img id={i} alt=\"{alt_text}\"/>"));
+                summary.push((priority, format!("<img id={i} alt=\"{alt_text}\"/>")));
             }
             "A" => {
                 let Some(inner_text) = inner_text else {
-                    continue
+                    continue;
                 };
 
-                let Some(href) = element.attribute("href").await? else {
-                    continue
+                let Some(href) = element.attribute("href") else {
+                    continue;
                 };
 
-                summary.push(format!("<link id={i} href={href}>{inner_text}<link>",));
+                summary.push((
+                    priority,
+                    format!("<link id={i} href={href}>{inner_text}<link>"),
+                ));
             }
-            "INPUT" => {
-                let Some(placeholder) = element.attribute("placeholder").await? else {
-                    continue
-                };
-
-                summary.push(format!("<input id={i}>{placeholder}</input>"));
+            "INPUT" | "TEXTAREA" | "SELECT" => {
+                summary.extend(describe_control(element, &i).map(|line| (priority, line)));
             }
             _ => {}
         }
     }
 
-    Ok(summary.join("\n"))
+    let mut summary = fit_to_budget(summary, options.max_tokens);
+    if options.viewport_only {
+        add_hidden_markers(&mut summary, viewport, hidden_above, hidden_below);
+    }
+
+    summary.join("\n")
+}
+
+/// Describes a form control with its type, current value and options, or `None` for hidden inputs.
+fn describe_control(element: &SnapshotElement, i: &str) -> Option<String> {
+    let mut attributes = vec![format!("id={i}")];
+
+    match element.tag.as_str() {
+        "SELECT" => {
+            attributes.extend(value_attribute(element));
+            attributes.push(format!(
+                "options={}",
+                serde_json::Value::from(element.options.clone())
+            ));
+
+            Some(format!(
+                "<select {}>{}</select>",
+                attributes.join(" "),
+                control_name(element)
+            ))
+        }
+        "TEXTAREA" => {
+            attributes.extend(value_attribute(element));
+
+            Some(format!(
+                "<textarea {}>{}</textarea>",
+                attributes.join(" "),
+                control_name(element)
+            ))
+        }
+        _ => {
+            let kind = element
+                .attribute("type")
+                .unwrap_or("text")
+                .to_ascii_lowercase();
+
+            match kind.as_str() {
+                "hidden" => None,
+                "submit" | "button" | "reset" => {
+                    let caption = element.value.as_deref().unwrap_or(&kind);
+
+                    Some(format!("<button id={i}>{caption}</button>"))
+                }
+                _ => {
+                    if kind != "text" {
+                        attributes.push(format!("type={kind}"));
+                    }
+                    match element.checked {
+                        Some(checked) => attributes.push(format!("checked={checked}")),
+                        None => attributes.extend(value_attribute(element)),
+                    }
+
+                    Some(format!(
+                        "<input {}>{}</input>",
+                        attributes.join(" "),
+                        control_name(element)
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The name of a form control, taken from its label, placeholder or name, in that order.
+fn control_name(element: &SnapshotElement) -> &str {
+    element
+        .label
+        .as_deref()
+        .filter(|label| !label.is_empty())
+        .or_else(|| element.attribute("placeholder"))
+        .or_else(|| element.attribute("name"))
+        .unwrap_or_default()
+}
+
+/// The current value of a form control as an attribute, unless it is empty.
+fn value_attribute(element: &SnapshotElement) -> Option<String> {
+    element
+        .value
+        .as_deref()
+        .filter(|value| !value.is_empty())
+        .map(|value| format!("value=\"{value}\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_smaller_lines_after_one_that_does_not_fit() {
+        let huge = format!("<select id=0 options={:?}>Country</select>", ["x"; 400]);
+        let lines = vec![
+            (Priority::Control, huge),
+            (Priority::Link, String::from("<link id=1 href=/a>A</link>")),
+            (Priority::Text, String::from("<p id=2>Hello</p>")),
+        ];
+
+        let summary = fit_to_budget(lines, Some(100));
+
+        assert_eq!(
+            summary,
+            [
+                "<link id=1 href=/a>A</link>",
+                "<p id=2>Hello</p>",
+                "[1 less important elements were left out to keep the page description short]",
+            ]
+        );
+    }
 }
@@ -1,10 +1,28 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+#![allow(clippy::multiple_crate_versions)]
 
+mod accessibility;
+mod action;
 mod agent;
+pub mod backend;
 pub mod browser;
+mod conversation;
+mod history;
 mod interpreter;
-mod openai;
+mod report;
+mod snapshot;
+mod tabs;
+mod tokens;
 
-pub use agent::Action;
-pub use interpreter::translate;
-pub use openai::Conversation;
+pub use accessibility::translate_accessibility;
+pub use action::{Action, ActionError, ParseError, Protocol, ScrollDirection};
+pub use agent::{Agent, AgentBuilder, StepOutcome, DEFAULT_START_URL};
+pub use backend::{ModelConfig, Usage};
+pub use conversation::Conversation;
+pub use history::HistoryPolicy;
+pub use interpreter::{
+    translate, Priority, TranslateOptions, Translator, VisibilityFilter, SELECTOR,
+};
+pub use report::RunReport;
+pub use snapshot::{Bounds, DomSnapshot, SnapshotElement, ID_ATTRIBUTE};
+pub use tokens::count_tokens;
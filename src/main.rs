@@ -1,33 +1,365 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use std::path::Path;
-use tracing::{debug, info, trace, Level};
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+use tracing::{debug, trace, Level};
 use tracing_subscriber::{
     prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt, EnvFilter,
 };
+use url::Url;
 
-use browser_agent::{browser, translate, Action, Conversation};
+use browser_agent::{
+    backend::{AnthropicBackend, ChatBackend, LocalBackend, OpenAiBackend, RetryPolicy},
+    browser::{self, LaunchOptions, ScreenSize, SettleOptions},
+    Agent, HistoryPolicy, ModelConfig, Protocol, StepOutcome, TranslateOptions, Translator,
+    VisibilityFilter, DEFAULT_START_URL,
+};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
+// Doc comments here are help text for the terminal, not Markdown.
+#[allow(clippy::struct_excessive_bools, clippy::doc_markdown)]
 struct Cli {
+    /// The goal for the agent to achieve. If omitted, the agent sets (and keeps changing) its own goal.
+    goal: Option<String>,
+
+    /// The URL of the page the agent starts on
+    #[arg(long, default_value = DEFAULT_START_URL)]
+    start_url: Url,
+
     /// Whether to show the browser window. Warning: this makes the agent more unreliable.
     #[arg(long)]
     visual: bool,
 
+    /// Drive an already running Chrome instead of launching one, given its WebSocket debugger URL or its remote debugging address
+    #[arg(long, value_name = "URL", conflicts_with = "visual")]
+    connect: Option<String>,
+
+    /// The Chrome or Chromium executable to launch (by default, CHROME, PATH and the usual install locations are searched)
+    #[arg(long, value_name = "PATH", conflicts_with = "connect")]
+    chrome_path: Option<PathBuf>,
+
+    /// Where to download Chromium to when no browser is installed, with the fetcher feature [default: ./browser]
+    #[arg(long, value_name = "DIR")]
+    browser_dir: Option<PathBuf>,
+
+    /// The browser profile to use, which keeps cookies and logins between runs [default: ./user_data]
+    #[arg(long, value_name = "DIR", conflicts_with = "connect")]
+    user_data_dir: Option<PathBuf>,
+
+    /// Use a fresh temporary profile, deleted when the agent exits; lets several agents run from the same directory
+    #[arg(long, conflicts_with_all = ["user_data_dir", "connect"])]
+    ephemeral: bool,
+
+    /// The size of the browser window, like 1280x800
+    #[arg(long, value_name = "WIDTHxHEIGHT", conflicts_with = "connect")]
+    window_size: Option<ScreenSize>,
+
+    /// The size of the area pages are rendered in, like 390x844 for a phone
+    #[arg(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        default_value = "800x600",
+        conflicts_with = "connect"
+    )]
+    viewport: ScreenSize,
+
+    /// The number of device pixels per CSS pixel
+    #[arg(long, conflicts_with = "connect")]
+    device_scale_factor: Option<f64>,
+
+    /// Pretend to be a mobile device with a touch screen, which many sites serve a simpler layout to
+    #[arg(long, conflicts_with = "connect")]
+    mobile: bool,
+
+    /// The language pages see, like en-GB
+    #[arg(long, conflicts_with = "connect")]
+    locale: Option<String>,
+
+    /// The time zone pages see, like Europe/Madrid; set through the browser's TZ variable, which Windows ignores
+    #[arg(long, conflicts_with = "connect")]
+    timezone: Option<String>,
+
+    /// The user agent to send instead of Chrome's own
+    #[arg(long, conflicts_with = "connect")]
+    user_agent: Option<String>,
+
+    /// An extra command line argument for Chrome, can be used multiple times
+    #[arg(
+        long,
+        value_name = "ARG",
+        allow_hyphen_values = true,
+        conflicts_with = "connect"
+    )]
+    chrome_arg: Vec<String>,
+
+    /// The API used to talk to the model
+    #[arg(long, value_enum, default_value_t = Backend::Openai)]
+    backend: Backend,
+
+    /// The model to use (defaults to gpt-4, claude-3-5-sonnet-latest or llama3, depending on the backend)
+    #[arg(long)]
+    model: Option<String>,
+
+    /// The sampling temperature; lower values make runs easier to reproduce [default: 0.7]
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    /// The nucleus sampling probability mass
+    #[arg(long)]
+    top_p: Option<f32>,
+
+    /// The maximum number of tokens the model may generate for each reply [default: 500]
+    #[arg(long)]
+    max_tokens: Option<u32>,
+
+    /// The seed for sampling, for backends that support one (openai and local)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// A sequence that ends the model's reply, can be used multiple times
+    #[arg(long, value_name = "SEQUENCE")]
+    stop: Vec<String>,
+
+    /// A TOML file setting any of the model options above, or --browser-dir, --user-data-dir and --ephemeral; flags take precedence over it
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// The base URL of the backend's API, for proxies and self-hosted servers
+    #[arg(long)]
+    base_url: Option<String>,
+
+    /// The format the model replies in, either "text" or "json"
+    #[arg(long, default_value = "text")]
+    protocol: Protocol,
+
+    /// The maximum number of actions to take before giving up
+    #[arg(long)]
+    max_steps: Option<usize>,
+
+    /// The maximum number of seconds to run for before giving up
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// The maximum number of tokens to use across all requests before giving up
+    #[arg(long)]
+    max_tokens_total: Option<u64>,
+
+    /// The number of consecutive failed actions to tolerate before giving up
+    #[arg(long, default_value_t = 3)]
+    max_errors: usize,
+
+    /// How many times to retry a request to the model that was rate limited, failed on the server or timed out
+    #[arg(long, default_value_t = 3)]
+    max_retries: u32,
+
+    /// How long to wait before the first retry; the wait doubles with every retry
+    #[arg(long, value_name = "SECONDS", default_value_t = 1)]
+    retry_delay: u64,
+
+    /// The longest to wait between two retries, even if the server asks for longer
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    max_retry_delay: u64,
+
+    /// How long a request to the model may take before it is retried
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    request_timeout: u64,
+
+    /// The longest to wait for a page to settle after each action
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    settle_timeout: u64,
+
+    /// How long the network and the page must stay quiet for it to count as settled
+    #[arg(long, value_name = "MILLISECONDS", default_value_t = 500)]
+    settle_idle_time: u64,
+
+    /// How many requests may still be in flight on a settled page, for long polls and the like
+    #[arg(long, value_name = "COUNT", default_value_t = 2)]
+    settle_max_requests: usize,
+
+    /// The maximum number of tokens of earlier steps to send with each request
+    #[arg(long, default_value_t = 3000)]
+    history_tokens: usize,
+
+    /// How many of the most recent page descriptions to send in full, including the current one
+    #[arg(long, default_value_t = 1)]
+    full_pages: usize,
+
+    /// Ask the model to summarise the steps that no longer fit in the history
+    #[arg(long)]
+    summarize_history: bool,
+
     /// Set the verbosity level, can be used multiple times
     #[arg(short, action = clap::ArgAction::Count)]
     verbosity: u8,
 
+    /// How pages are described to the model, either "dom" or "accessibility" (finds ARIA widgets, selects and more)
+    #[arg(long, default_value = "dom")]
+    translator: Translator,
+
     /// Whether to include text from the page in the prompt
     #[arg(long)]
     include_page_content: bool,
+
+    /// Only include elements in (or near) the visible part of the page in the prompt
+    #[arg(long)]
+    viewport_only: bool,
+
+    /// The maximum number of tokens each page description may use; the least important elements are left out first
+    #[arg(long, default_value_t = 3000)]
+    max_page_tokens: usize,
+
+    /// What to do with hidden elements, and elements covered by others: "off", "mark" or "drop"
+    #[arg(long, default_value = "off")]
+    visibility: VisibilityFilter,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Backend {
+    /// `OpenAI`, or any server implementing its chat completions API.
+    Openai,
+    /// The Anthropic Messages API.
+    Anthropic,
+    /// A local Ollama (or compatible) server.
+    Local,
+}
+
+/// The keys of the config file that configure the browser, rather than the model.
+const BROWSER_KEYS: &[&str] = &["browser_dir", "user_data_dir", "ephemeral"];
+
+/// Settings read from the file given with `--config`. Flags take precedence over them.
+#[derive(Debug, Default)]
+struct ConfigFile {
+    /// The model, and the sampling settings.
+    model: ModelConfig,
+    /// Where the browser is downloaded to, and keeps its profile.
+    browser: BrowserSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BrowserSettings {
+    browser_dir: Option<PathBuf>,
+    user_data_dir: Option<PathBuf>,
+    ephemeral: bool,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}.", path.display()))?;
+        let parse = || format!("Failed to parse {}.", path.display());
+
+        // Both kinds of settings live at the top level, so split them before checking for unknown keys.
+        let mut model: toml::Table = toml::from_str(&contents).with_context(parse)?;
+        let browser: toml::Table = BROWSER_KEYS
+            .iter()
+            .filter_map(|key| model.remove(*key).map(|value| ((*key).to_string(), value)))
+            .collect();
+
+        Ok(Self {
+            model: toml::Value::Table(model).try_into().with_context(parse)?,
+            browser: toml::Value::Table(browser).try_into().with_context(parse)?,
+        })
+    }
+}
+
+impl Cli {
+    /// The settings from the config file, or the defaults if none was given.
+    fn config_file(&self) -> Result<ConfigFile> {
+        self.config
+            .as_deref()
+            .map_or_else(|| Ok(ConfigFile::default()), ConfigFile::read)
+    }
+
+    /// The model settings from the config file, overridden by the ones given as flags.
+    fn model_config(&self, mut config: ModelConfig) -> ModelConfig {
+        if let Some(model) = &self.model {
+            config.model = Some(model.clone());
+        }
+        if let Some(temperature) = self.temperature {
+            config.temperature = Some(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            config.top_p = Some(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            config.max_tokens = max_tokens;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+        if !self.stop.is_empty() {
+            config.stop.clone_from(&self.stop);
+        }
+
+        config
+    }
+
+    fn launch_options(&self) -> LaunchOptions {
+        LaunchOptions {
+            headless: !self.visual,
+            window_size: self.window_size,
+            viewport: self.viewport,
+            device_scale_factor: self.device_scale_factor,
+            mobile: self.mobile,
+            locale: self.locale.clone(),
+            timezone: self.timezone.clone(),
+            user_agent: self.user_agent.clone(),
+            args: self.chrome_arg.clone(),
+        }
+    }
+
+    /// Decides when a page has settled after an action.
+    const fn settle_options(&self) -> SettleOptions {
+        SettleOptions {
+            max_wait: Duration::from_secs(self.settle_timeout),
+            idle_time: Duration::from_millis(self.settle_idle_time),
+            max_in_flight: self.settle_max_requests,
+        }
+    }
+
+    fn backend(&self, model: Option<&str>) -> Box<dyn ChatBackend> {
+        let base_url = self.base_url.as_deref();
+
+        match self.backend {
+            Backend::Openai => {
+                let mut backend = OpenAiBackend::new(model.unwrap_or("gpt-4"));
+                if let Some(base_url) = base_url {
+                    backend = backend.with_base_url(base_url);
+                }
+
+                Box::new(backend)
+            }
+            Backend::Anthropic => {
+                let mut backend =
+                    AnthropicBackend::new(model.unwrap_or("claude-3-5-sonnet-latest"));
+                if let Some(base_url) = base_url {
+                    backend = backend.with_base_url(base_url);
+                }
+
+                Box::new(backend)
+            }
+            Backend::Local => {
+                let mut backend = LocalBackend::new(model.unwrap_or("llama3"));
+                if let Some(base_url) = base_url {
+                    backend = backend.with_base_url(base_url);
+                }
+
+                Box::new(backend)
+            }
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
     let args = Cli::parse();
 
     tracing_subscriber::registry()
@@ -49,62 +381,149 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let mut conversation = Conversation::new();
-    let mut browser = browser::init(
-        Path::new("./browser"),
-        Path::new("./user_data"),
-        args.visual,
-    )
-    .await?;
-
-    let page = browser.new_page("https://duckduckgo.com/").await?;
+    let config = args.config_file()?;
+    let model_config = args.model_config(config.model);
 
-    loop {
-        browser::wait_for_page(&page).await;
+    let browser_dir = args
+        .browser_dir
+        .clone()
+        .or(config.browser.browser_dir)
+        .unwrap_or_else(|| PathBuf::from("./browser"));
+    let ephemeral = args.ephemeral || (config.browser.ephemeral && args.user_data_dir.is_none());
+    // Kept until the agent has closed the browser, so the profile is only deleted once the browser is gone.
+    let profile = if ephemeral {
+        Some(tempfile::Builder::new().prefix("run-wild-").tempdir()?)
+    } else {
+        None
+    };
+    let user_data_dir = match &profile {
+        Some(profile) => profile.path().to_path_buf(),
+        None => args
+            .user_data_dir
+            .clone()
+            .or(config.browser.user_data_dir)
+            .unwrap_or_else(|| PathBuf::from("./user_data")),
+    };
+    debug!("Using the model settings {model_config:?}.");
+    debug!(
+        "Using the browser directory {} and the profile in {}.",
+        browser_dir.display(),
+        user_data_dir.display()
+    );
 
-        let url = page.url().await?.expect("Page should have a URL");
-        let elements = page.find_elements("p, button, input, a, img").await?;
+    let builder = match &args.connect {
+        Some(url) => Agent::builder().attached_browser(browser::connect(url).await?),
+        None => Agent::builder().browser(
+            browser::init(
+                args.chrome_path.as_deref(),
+                &browser_dir,
+                &user_data_dir,
+                &args.launch_options(),
+            )
+            .await?,
+        ),
+    };
 
-        info!("Current URL: {}", url);
-        debug!("Found {} elements.", elements.len());
+    let mut builder = builder
+        .backend(args.backend(model_config.model.as_deref()))
+        .model_config(model_config)
+        .protocol(args.protocol)
+        .start_url(args.start_url.clone())
+        .max_errors(args.max_errors)
+        .retry_policy(RetryPolicy {
+            max_retries: args.max_retries,
+            initial_delay: Duration::from_secs(args.retry_delay),
+            max_delay: Duration::from_secs(args.max_retry_delay),
+            request_timeout: Some(Duration::from_secs(args.request_timeout)),
+        })
+        .settle_options(args.settle_options())
+        .history_policy(HistoryPolicy {
+            max_tokens: args.history_tokens,
+            full_pages: args.full_pages,
+            summarize: args.summarize_history,
+        })
+        .translate_options(TranslateOptions {
+            translator: args.translator,
+            include_paragraphs: args.include_page_content,
+            viewport_only: args.viewport_only,
+            visibility: args.visibility,
+            max_tokens: Some(args.max_page_tokens),
+        });
+    if let Some(goal) = &args.goal {
+        builder = builder.goal(goal);
+    }
+    if let Some(max_steps) = args.max_steps {
+        builder = builder.max_steps(max_steps);
+    }
+    if let Some(timeout) = args.timeout {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(max_tokens_total) = args.max_tokens_total {
+        builder = builder.max_total_tokens(max_tokens_total);
+    }
 
-        let page_content = translate(&elements, args.include_page_content).await?;
-        let action = conversation.request_action(&url, &page_content).await?;
+    let exit_code = run(builder.build().await?).await;
+    drop(profile);
 
-        match action {
-            Action::Click(id) => {
-                let element = elements
-                    .get(id)
-                    .ok_or_else(|| anyhow!("Failed to find element."))?;
+    exit_code
+}
 
-                info!(
-                    "Clicking on \"{}\".",
-                    element
-                        .inner_text()
-                        .await?
-                        .expect("Target should have text.")
-                );
+/// Runs the agent until it's done or interrupted, then closes the browser, even if the run failed.
+async fn run(mut agent: Agent) -> Result<ExitCode> {
+    // Runs without limits are usually stopped with Ctrl-C, which should still clean up after itself.
+    let outcome = tokio::select! {
+        outcome = agent.run() => outcome.map(Some),
+        _ = tokio::signal::ctrl_c() => Ok(None),
+    };
+    eprintln!("{}", agent.report());
+    let interrupted = matches!(outcome, Ok(None));
+    let exit_code = outcome.map(|outcome| {
+        outcome.map_or_else(
+            || {
+                eprintln!("Stopped: interrupted.");
+                ExitCode::from(130)
+            },
+            |outcome| exit_code(outcome, &agent),
+        )
+    });
 
-                element.click().await?;
-            }
-            Action::Type(id, text) => {
-                let element = elements
-                    .get(id)
-                    .ok_or_else(|| anyhow!("Failed to find element."))?;
+    // Close the browser so an ephemeral profile can be deleted.
+    match agent.close().await {
+        // Ctrl-C reaches the browser too, so it may be gone already.
+        Err(error) if interrupted => {
+            debug!("Failed to close the browser after the interrupt: {error}");
+        }
+        closed => closed?,
+    }
+    trace!("Agent closed.");
 
-                info!("Typing \"{}\" into input.", text);
+    exit_code
+}
 
-                element.type_str(text).await?;
-                element.press_key("Enter").await?;
-            }
-            Action::Goal(text) => {
-                println!("{text}");
-                break;
-            }
-        };
+/// Reports how the run ended, and picks the matching exit code.
+fn exit_code(outcome: StepOutcome, agent: &Agent) -> ExitCode {
+    match outcome {
+        StepOutcome::Finished(goal) => {
+            println!("{goal}");
+            ExitCode::SUCCESS
+        }
+        StepOutcome::StepLimitReached => {
+            eprintln!("Stopped: took {} steps without finishing.", agent.steps());
+            ExitCode::from(3)
+        }
+        StepOutcome::TimedOut => {
+            eprintln!("Stopped: ran out of time without finishing.");
+            ExitCode::from(4)
+        }
+        StepOutcome::TokenBudgetExhausted => {
+            eprintln!(
+                "Stopped: used {} tokens without finishing.",
+                agent.conversation().usage().total_tokens()
+            );
+            ExitCode::from(5)
+        }
+        StepOutcome::Performed(_) | StepOutcome::Failed(_) => {
+            unreachable!("run only returns final outcomes")
+        }
     }
-
-    browser.close().await?;
-    trace!("Browser closed.");
-    Ok(())
 }
@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::fmt;
+
+use crate::{
+    backend::{Price, Usage},
+    Conversation,
+};
+
+/// How many steps a run took, the tokens it used, and what they cost.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    /// The number of steps taken.
+    pub steps: usize,
+    /// The model the requests were sent to, if the backend reported it.
+    pub model: Option<String>,
+    /// The tokens used by every request.
+    pub usage: Usage,
+    /// The tokens used by each step that reached the model, oldest first.
+    pub step_usage: Vec<Usage>,
+    /// The estimated cost of the run in US dollars, if the model's price is known.
+    pub cost: Option<f64>,
+}
+
+impl RunReport {
+    /// Summarise the usage of a conversation.
+    ///
+    /// # Arguments
+    ///
+    /// * `steps` - The number of steps taken.
+    /// * `conversation` - The conversation the steps were taken in.
+    #[must_use]
+    pub fn new(steps: usize, conversation: &Conversation) -> Self {
+        let model = conversation.model().map(String::from);
+        let usage = conversation.usage();
+
+        Self {
+            steps,
+            cost: model
+                .as_deref()
+                .and_then(Price::of_model)
+                .map(|price| usage.cost(price)),
+            model,
+            usage,
+            step_usage: conversation.step_usage().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Took {} steps and used {} tokens ({} prompt, {} completion)",
+            self.steps,
+            self.usage.total_tokens(),
+            self.usage.prompt_tokens,
+            self.usage.completion_tokens
+        )?;
+
+        match (&self.model, self.cost) {
+            (Some(model), Some(cost)) => write!(f, ", costing about ${cost:.2} with {model}."),
+            (Some(model), None) => write!(f, ", the price of {model} is unknown."),
+            (None, _) => write!(f, "."),
+        }
+    }
+}
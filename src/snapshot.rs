@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use chromiumoxide::{Element, Page};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::ActionError;
+
+/// The attribute that links the elements of a snapshot back to their DOM nodes.
+pub const ID_ATTRIBUTE: &str = "data-agent-id";
+
+/// Collects everything needed to describe the matching elements, in a single round trip.
+///
+/// Each element is marked with its id, so it can be found again when an action refers to it. Elements
+/// keep the id they were given by earlier snapshots, and new ones are numbered after the highest id
+/// ever handed out on the page, so an id never moves to a different node.
+const SNAPSHOT_SCRIPT: &str = r"(selector, attribute) => {
+    const marked = Array.from(document.querySelectorAll(`[${attribute}]`), e => Number(e.getAttribute(attribute)));
+    let next = Math.max(window.__agentNextId ?? 0, ...marked.map(id => id + 1));
+    const seen = new Set();
+
+    const elements = Array.from(document.querySelectorAll(selector), e => {
+        let id = Number.parseInt(e.getAttribute(attribute), 10);
+        // Cloned nodes copy the attribute of the original, so they need an id of their own.
+        if (Number.isNaN(id) || seen.has(id)) {
+            id = next++;
+            e.setAttribute(attribute, id);
+        }
+        seen.add(id);
+
+        const style = getComputedStyle(e);
+        const rect = e.getBoundingClientRect();
+        const rendered = e.getClientRects().length > 0;
+        const visible = rendered && rect.width > 0 && rect.height > 0
+            && style.visibility !== 'hidden' && Number(style.opacity) > 0;
+
+        // Whatever is on top at the element's center gets the click, which may be a banner or overlay.
+        const x = rect.x + rect.width / 2;
+        const y = rect.y + rect.height / 2;
+        const testable = visible && x >= 0 && y >= 0 && x < innerWidth && y < innerHeight;
+        const hit = testable ? document.elementFromPoint(x, y) : null;
+        const obscured = testable && (style.pointerEvents === 'none' || !(hit && (hit === e || e.contains(hit))));
+
+        return {
+            id,
+            tag: e.tagName,
+            text: e.innerText ?? null,
+            attributes: Object.fromEntries(Array.from(e.attributes, a => [a.name, a.value])),
+            bounds: rendered ? { x: rect.x, y: rect.y, width: rect.width, height: rect.height } : null,
+            visible,
+            obscured,
+            label: e.labels?.length
+                ? Array.from(e.labels, l => l.innerText.trim()).join(' ')
+                : e.getAttribute('aria-label'),
+            value: e instanceof HTMLSelectElement
+                ? e.selectedOptions[0]?.text.trim() ?? null
+                : e instanceof HTMLInputElement || e instanceof HTMLTextAreaElement ? e.value : null,
+            checked: e instanceof HTMLInputElement && ['checkbox', 'radio'].includes(e.type) ? e.checked : null,
+            options: e instanceof HTMLSelectElement ? Array.from(e.options, o => o.text.trim()) : [],
+        };
+    });
+
+    window.__agentNextId = next;
+    return elements;
+}";
+
+/// The position and size of an element relative to the viewport, in CSS pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Bounds {
+    /// The distance from the left edge of the viewport.
+    pub x: f64,
+    /// The distance from the top edge of the viewport.
+    pub y: f64,
+    /// The width of the element.
+    pub width: f64,
+    /// The height of the element.
+    pub height: f64,
+}
+
+/// A single element, as it was when the snapshot was taken.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SnapshotElement {
+    /// The id the model uses to refer to the element.
+    pub id: usize,
+    /// The tag name, in upper case.
+    pub tag: String,
+    /// The rendered text of the element, if it has any.
+    pub text: Option<String>,
+    /// The attributes of the element.
+    pub attributes: HashMap<String, String>,
+    /// Where the element is, or `None` if it isn't rendered.
+    pub bounds: Option<Bounds>,
+    /// Whether the element is rendered, has a size, and is not hidden by its style.
+    pub visible: bool,
+    /// Whether clicking the center of the element would hit something else, like a cookie banner.
+    ///
+    /// Only elements inside the viewport can be tested, so this is `false` for the others.
+    #[serde(default)]
+    pub obscured: bool,
+    /// The text of the element's labels, or its ARIA label, for form controls.
+    pub label: Option<String>,
+    /// The current value of an input or text area, or the text of the selected option of a dropdown.
+    pub value: Option<String>,
+    /// Whether a checkbox or radio button is checked.
+    pub checked: Option<bool>,
+    /// The text of each option of a dropdown.
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+impl SnapshotElement {
+    /// The value of the given attribute, if the element has it.
+    #[must_use]
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+}
+
+/// The candidate elements of a page, collected with a single script instead of a CDP call per element.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DomSnapshot {
+    /// The elements, in document order.
+    pub elements: Vec<SnapshotElement>,
+}
+
+impl DomSnapshot {
+    /// Takes a snapshot of the elements matching the given selector.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - The page to take the snapshot of.
+    /// * `selector` - The CSS selector of the candidate elements.
+    ///
+    /// # Errors
+    ///
+    /// * If the snapshot script fails, or returns something unexpected.
+    pub async fn capture(page: &Page, selector: &str) -> Result<Self> {
+        let script = format!(
+            "({SNAPSHOT_SCRIPT})({}, {})",
+            serde_json::to_string(selector)?,
+            serde_json::to_string(ID_ATTRIBUTE)?
+        );
+
+        let elements = page
+            .evaluate(script)
+            .await
+            .context("Failed to take a snapshot of the page")?
+            .into_value()
+            .context("Failed to read the snapshot of the page")?;
+
+        Ok(Self { elements })
+    }
+
+    /// The element with the given id, if there is one.
+    #[must_use]
+    pub fn get(&self, id: usize) -> Option<&SnapshotElement> {
+        self.elements.iter().find(|element| element.id == id)
+    }
+
+    /// The ids of the elements in the snapshot, in ascending order.
+    #[must_use]
+    pub fn ids(&self) -> Vec<usize> {
+        let mut ids: Vec<_> = self.elements.iter().map(|element| element.id).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// The number of elements in the snapshot.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// Whether the snapshot has no elements.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Finds the DOM node behind the element with the given id, so it can be acted on.
+    ///
+    /// The node is looked up by its id attribute rather than its position, so changes to the page since
+    /// the snapshot was taken can't make the id point at a different node.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - The page the snapshot was taken of.
+    /// * `id` - The id of the element.
+    ///
+    /// # Errors
+    ///
+    /// * If the snapshot has no element with the given id.
+    /// * If the element has been removed from the page since the snapshot was taken.
+    pub async fn resolve(&self, page: &Page, id: usize) -> Result<Element, ActionError> {
+        if self.get(id).is_none() {
+            return Err(ActionError::UnknownElement {
+                id,
+                ids: self.ids(),
+            });
+        }
+
+        page.find_element(format!("[{ID_ATTRIBUTE}=\"{id}\"]"))
+            .await
+            .map_err(|_| ActionError::StaleElement(id))
+    }
+}
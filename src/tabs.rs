@@ -0,0 +1,319 @@
+use anyhow::Result;
+use chromiumoxide::{
+    cdp::browser_protocol::target::{
+        EventTargetCreated, EventTargetDestroyed, TargetId, TargetInfo,
+    },
+    Browser, Page,
+};
+use std::fmt::Write;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    task::JoinHandle,
+    time::{sleep, Duration},
+};
+use tokio_stream::StreamExt;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{
+    browser::{SettleOptions, Settled, Settler},
+    ActionError,
+};
+
+/// How many times to look for the page of a new tab, while the browser sets it up.
+const PAGE_LOOKUPS: usize = 20;
+
+/// How long to wait between two lookups of a new tab's page.
+const PAGE_LOOKUP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A tab the agent has open.
+#[derive(Debug)]
+struct Tab {
+    /// The page shown in the tab.
+    page: Page,
+    /// Waits for the page to settle after each action.
+    settler: Settler,
+}
+
+/// A change to the browser's targets, which include its tabs.
+#[derive(Debug)]
+enum TargetEvent {
+    Created(TargetInfo),
+    Destroyed(TargetId),
+}
+
+/// The tabs the agent has open, including the popups opened from them, and the one it is acting on.
+///
+/// Tabs opened by anyone else, like the user of a browser the agent is attached to, are left alone.
+#[derive(Debug)]
+pub struct Tabs {
+    /// The open tabs, in the order they were opened.
+    open: Vec<Tab>,
+    /// The index of the tab the agent is acting on.
+    active: usize,
+    /// Decides when a page has settled after an action.
+    settle_options: SettleOptions,
+    /// The targets created and destroyed since the last call to [`Tabs::settle`].
+    events: UnboundedReceiver<TargetEvent>,
+    /// Follows the browser's target events.
+    task: JoinHandle<()>,
+}
+
+impl Tabs {
+    /// Open the first tab, and start watching for the tabs opened from it.
+    ///
+    /// # Arguments
+    ///
+    /// * `browser` - The browser to open the tab in.
+    /// * `url` - The page to open.
+    /// * `settle_options` - Decides when a page has settled after an action.
+    ///
+    /// # Errors
+    ///
+    /// * If the browser's target events cannot be listened to.
+    /// * If the tab cannot be opened.
+    pub async fn open(browser: &Browser, url: &str, settle_options: SettleOptions) -> Result<Self> {
+        let created = browser.event_listener::<EventTargetCreated>().await?;
+        let destroyed = browser.event_listener::<EventTargetDestroyed>().await?;
+        let mut target_events = created
+            .map(|event| TargetEvent::Created(event.target_info.clone()))
+            .merge(destroyed.map(|event| TargetEvent::Destroyed(event.target_id.clone())));
+
+        let (sender, events) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            while let Some(event) = target_events.next().await {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let page = browser.new_page(url).await?;
+        let settler = Settler::start(&page, settle_options).await?;
+
+        Ok(Self {
+            open: vec![Tab { page, settler }],
+            active: 0,
+            settle_options,
+            events,
+            task,
+        })
+    }
+
+    /// The page in the tab the agent is acting on.
+    #[must_use]
+    pub fn active(&self) -> &Page {
+        &self.open[self.active].page
+    }
+
+    /// The pages in every open tab, in the order they were opened.
+    pub fn pages(&self) -> impl Iterator<Item = &Page> {
+        self.open.iter().map(|tab| &tab.page)
+    }
+
+    /// Waits for the active tab to settle, after switching to any popup it opened.
+    ///
+    /// # Arguments
+    ///
+    /// * `browser` - The browser the tabs are in.
+    ///
+    /// # Errors
+    ///
+    /// * If a popup cannot be listened to.
+    pub async fn settle(&mut self, browser: &Browser) -> Result<Settled> {
+        let settled = self.open[self.active].settler.wait().await;
+
+        // Popups have opened by the time the opener settles, so any that aren't tracked yet are new.
+        if self.adopt_popups(browser).await? {
+            return Ok(self.open[self.active].settler.wait().await);
+        }
+
+        Ok(settled)
+    }
+
+    /// Tracks the tabs opened from the agent's tabs since the last call, and forgets the ones that were closed.
+    ///
+    /// Returns whether the agent switched to a new tab.
+    async fn adopt_popups(&mut self, browser: &Browser) -> Result<bool> {
+        let mut opened = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                TargetEvent::Created(info) if info.r#type == "page" => opened.push(info),
+                TargetEvent::Created(_) => {}
+                TargetEvent::Destroyed(target_id) => {
+                    opened.retain(|info| info.target_id != target_id);
+                    self.forget(&target_id);
+                }
+            }
+        }
+
+        let mut switched = false;
+        for info in opened {
+            let from_our_tab = info
+                .opener_id
+                .as_ref()
+                .is_some_and(|opener| self.open.iter().any(|tab| tab.page.target_id() == opener));
+            if !from_our_tab {
+                continue;
+            }
+
+            let Some(page) = find_page(browser, &info.target_id).await else {
+                debug!("The tab opened at {} could not be found.", info.url);
+                continue;
+            };
+            let settler = Settler::start(&page, self.settle_options).await?;
+
+            info!("Switching to the tab opened at {}.", info.url);
+            self.open.push(Tab { page, settler });
+            self.active = self.open.len() - 1;
+            switched = true;
+        }
+
+        Ok(switched)
+    }
+
+    /// Stops tracking a tab that was closed by its page.
+    fn forget(&mut self, target_id: &TargetId) {
+        let Some(index) = self
+            .open
+            .iter()
+            .position(|tab| tab.page.target_id() == target_id)
+        else {
+            return;
+        };
+
+        // There's always a tab to act on; if the last one closed itself, the next action reports it.
+        if self.open.len() > 1 {
+            debug!("Tab {index} was closed by its page.");
+            self.remove(index);
+        }
+    }
+
+    /// Removes a tab, and acts on the one before it if it was the active tab.
+    fn remove(&mut self, index: usize) -> Tab {
+        let tab = self.open.remove(index);
+        if self.active > index || (self.active == index && index > 0) {
+            self.active = self.active.saturating_sub(1);
+        }
+
+        tab
+    }
+
+    /// Act on a different tab.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The number of the tab, starting at 0.
+    ///
+    /// # Errors
+    ///
+    /// * If there is no such tab.
+    /// * If the tab cannot be brought to the front.
+    pub async fn switch(&mut self, index: usize) -> Result<(), ActionError> {
+        self.check(index)?;
+
+        info!("Switching to tab {index}.");
+        self.active = index;
+        self.active().bring_to_front().await?;
+
+        Ok(())
+    }
+
+    /// Close a tab. If it was the active tab, the agent acts on the one before it.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The number of the tab, starting at 0.
+    ///
+    /// # Errors
+    ///
+    /// * If there is no such tab, or it is the only one.
+    /// * If the tab cannot be closed.
+    pub async fn close(&mut self, index: usize) -> Result<(), ActionError> {
+        self.check(index)?;
+        if self.open.len() == 1 {
+            return Err(ActionError::Failed(format!(
+                "tab {index} is the only tab open, so it cannot be closed"
+            )));
+        }
+
+        info!("Closing tab {index}.");
+        self.remove(index).page.close().await?;
+        self.active().bring_to_front().await?;
+
+        Ok(())
+    }
+
+    /// Open a page in a new tab, and act on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `browser` - The browser to open the tab in.
+    /// * `url` - The page to open.
+    ///
+    /// # Errors
+    ///
+    /// * If the tab cannot be opened.
+    pub async fn open_tab(&mut self, browser: &Browser, url: &Url) -> Result<(), ActionError> {
+        info!("Opening {url} in a new tab.");
+
+        let page = browser.new_page(url.as_str()).await?;
+        let settler = Settler::start(&page, self.settle_options).await?;
+        self.open.push(Tab { page, settler });
+        self.active = self.open.len() - 1;
+
+        Ok(())
+    }
+
+    /// Lists the open tabs for the model, or returns an empty string if there's only one.
+    ///
+    /// # Errors
+    ///
+    /// * If the title or URL of a tab cannot be read.
+    pub async fn describe(&self) -> Result<String> {
+        if self.open.len() == 1 {
+            return Ok(String::new());
+        }
+
+        let mut description = String::from("TABS:\n");
+        for (index, tab) in self.open.iter().enumerate() {
+            let title = tab.page.get_title().await?.unwrap_or_default();
+            let url = tab.page.url().await?.unwrap_or_default();
+            let current = if index == self.active { " current" } else { "" };
+
+            writeln!(description, "[{index}] \"{title}\" {url}{current}")?;
+        }
+        description.push('\n');
+
+        Ok(description)
+    }
+
+    const fn check(&self, index: usize) -> Result<(), ActionError> {
+        if index < self.open.len() {
+            Ok(())
+        } else {
+            Err(ActionError::UnknownTab {
+                tab: index,
+                count: self.open.len(),
+            })
+        }
+    }
+}
+
+impl Drop for Tabs {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Looks up the page of a new tab, giving the browser some time to set it up.
+async fn find_page(browser: &Browser, target_id: &TargetId) -> Option<Page> {
+    for _ in 0..PAGE_LOOKUPS {
+        if let Ok(page) = browser.get_page(target_id.clone()).await {
+            return Some(page);
+        }
+        sleep(PAGE_LOOKUP_INTERVAL).await;
+    }
+
+    None
+}
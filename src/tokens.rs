@@ -0,0 +1,9 @@
+use tiktoken_rs::cl100k_base_singleton;
+
+/// Counts the tokens in the given text, using the `cl100k_base` encoding of GPT-4 and GPT-3.5.
+///
+/// Other models split text a little differently, but this is close enough to budget with.
+#[must_use]
+pub fn count_tokens(text: &str) -> usize {
+    cl100k_base_singleton().lock().encode_ordinary(text).len()
+}
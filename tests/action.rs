@@ -0,0 +1,186 @@
+use browser_agent::{Action, ActionError, ParseError, ScrollDirection};
+
+#[test]
+fn parses_the_text_grammar() {
+    let cases = [
+        ("CLICK 3", Action::Click(3)),
+        (
+            "TYPE 2 \"hello world\"",
+            Action::Type(2, String::from("hello world")),
+        ),
+        ("SCROLL UP", Action::Scroll(ScrollDirection::Up)),
+        ("SCROLL DOWN", Action::Scroll(ScrollDirection::Down)),
+        ("SCROLL 4", Action::ScrollTo(4)),
+        (
+            "GOTO https://example.com/",
+            Action::Navigate("https://example.com/".parse().unwrap()),
+        ),
+        ("BACK", Action::Back),
+        ("FORWARD", Action::Forward),
+        ("RELOAD", Action::Reload),
+        (
+            "GOAL \"Visit 10 webpages.\"",
+            Action::Goal(String::from("Visit 10 webpages.")),
+        ),
+        (
+            "DONE \"Found a flight.\"",
+            Action::Done(String::from("Found a flight.")),
+        ),
+    ];
+
+    for (reply, action) in cases {
+        assert_eq!(Action::parse(reply).unwrap(), action, "{reply}");
+    }
+}
+
+#[test]
+fn tolerates_chatter_around_commands() {
+    assert_eq!(Action::parse("I will CLICK 3").unwrap(), Action::Click(3));
+    assert_eq!(Action::parse("CLICK [5].").unwrap(), Action::Click(5));
+    assert_eq!(
+        Action::parse("GOTO example.com").unwrap(),
+        Action::Navigate("https://example.com/".parse().unwrap())
+    );
+}
+
+#[test]
+fn prefers_a_command_at_the_start_of_a_line() {
+    assert_eq!(
+        Action::parse("The search box is 2, so I'll use it.\nTYPE 2 \"CLICK here\"").unwrap(),
+        Action::Type(2, String::from("CLICK here"))
+    );
+    assert_eq!(
+        Action::parse("GOAL \"Go BACK to the list\"").unwrap(),
+        Action::Goal(String::from("Go BACK to the list"))
+    );
+    assert_eq!(
+        Action::parse("CLICK 3\nThat should take us BACK to the results.").unwrap(),
+        Action::Click(3)
+    );
+}
+
+#[test]
+fn rejects_replies_with_more_than_one_command() {
+    assert!(matches!(
+        Action::parse("I'll go BACK after I CLICK 3"),
+        Err(ParseError::MultipleCommands(commands)) if commands == ["BACK", "CLICK"]
+    ));
+    assert!(matches!(
+        Action::parse("My GOAL is to CLICK 3"),
+        Err(ParseError::MultipleCommands(_))
+    ));
+    assert!(matches!(
+        Action::parse("CLICK 3\nBACK"),
+        Err(ParseError::MultipleCommands(commands)) if commands == ["CLICK", "BACK"]
+    ));
+}
+
+#[test]
+fn reports_typed_errors() {
+    assert!(matches!(Action::parse(""), Err(ParseError::Empty)));
+    assert!(matches!(Action::parse("hmm"), Err(ParseError::UnknownCommand(c)) if c == "hmm"));
+    assert!(matches!(
+        Action::parse("CLICK"),
+        Err(ParseError::MissingArgument {
+            command: "CLICK",
+            ..
+        })
+    ));
+    assert!(matches!(
+        Action::parse("CLICK button"),
+        Err(ParseError::InvalidId(_))
+    ));
+}
+
+#[test]
+fn parses_json_actions() {
+    assert_eq!(
+        Action::parse(r#"{"action": "type", "id": 1, "text": "rust"}"#).unwrap(),
+        Action::Type(1, String::from("rust"))
+    );
+    assert_eq!(
+        Action::parse("```json\n{\"action\": \"scroll\", \"direction\": \"up\"}\n```").unwrap(),
+        Action::Scroll(ScrollDirection::Up)
+    );
+    assert_eq!(
+        Action::parse(r#"{"action": "done", "text": "Found it."}"#).unwrap(),
+        Action::Done(String::from("Found it."))
+    );
+    assert!(matches!(
+        Action::parse(r#"{"action": "fly"}"#),
+        Err(ParseError::Json(_))
+    ));
+    assert_eq!(
+        Action::parse("GOAL \"Return {braces}\"").unwrap(),
+        Action::Goal(String::from("Return {braces}"))
+    );
+}
+
+#[test]
+fn lists_the_valid_ids_of_unknown_elements() {
+    let error = ActionError::UnknownElement {
+        id: 42,
+        ids: vec![0, 1, 2, 3, 7, 9, 10],
+    };
+    assert_eq!(
+        error.to_string(),
+        "element 42 does not exist; valid ids are 0-3, 7, 9-10"
+    );
+
+    let error = ActionError::UnknownElement { id: 0, ids: vec![] };
+    assert_eq!(
+        error.to_string(),
+        "element 0 does not exist; there are no elements on the page"
+    );
+}
+
+#[test]
+fn parses_form_commands() {
+    assert_eq!(
+        Action::parse("SELECT 4 \"Green\"").unwrap(),
+        Action::Select(4, String::from("Green"))
+    );
+    assert_eq!(Action::parse("CHECK 2").unwrap(), Action::Check(2, true));
+    assert_eq!(Action::parse("UNCHECK 2").unwrap(), Action::Check(2, false));
+    assert_eq!(Action::parse("CLEAR 7").unwrap(), Action::Clear(7));
+    assert_eq!(
+        Action::parse(r#"{"action": "select", "id": 4, "option": "Green"}"#).unwrap(),
+        Action::Select(4, String::from("Green"))
+    );
+    assert_eq!(
+        Action::parse(r#"{"action": "check", "id": 2, "checked": false}"#).unwrap(),
+        Action::Check(2, false)
+    );
+    assert_eq!(
+        Action::parse(r#"{"action": "clear", "id": 7}"#).unwrap(),
+        Action::Clear(7)
+    );
+}
+
+#[test]
+fn parses_tab_commands() {
+    assert_eq!(Action::parse("SWITCHTAB 1").unwrap(), Action::SwitchTab(1));
+    assert_eq!(Action::parse("CLOSETAB [0]").unwrap(), Action::CloseTab(0));
+    assert_eq!(
+        Action::parse("NEWTAB example.com").unwrap(),
+        Action::NewTab("https://example.com/".parse().unwrap())
+    );
+    assert_eq!(
+        Action::parse(r#"{"action": "switch_tab", "tab": 2}"#).unwrap(),
+        Action::SwitchTab(2)
+    );
+    assert_eq!(
+        Action::parse(r#"{"action": "new_tab", "url": "https://example.com/"}"#).unwrap(),
+        Action::NewTab("https://example.com/".parse().unwrap())
+    );
+    assert!(matches!(
+        Action::parse("CLOSETAB last"),
+        Err(ParseError::InvalidTab(_))
+    ));
+
+    let error = ActionError::UnknownTab { tab: 3, count: 2 };
+    assert_eq!(
+        error.to_string(),
+        "tab 3 does not exist; there are 2 tabs open, numbered from 0"
+    );
+}
@@ -0,0 +1,582 @@
+mod common;
+
+use browser_agent::{
+    backend::{Message, MockBackend},
+    browser::{self, LaunchOptions, ScreenSize, SettleOptions, Settled, Settler},
+    translate_accessibility, Action, ActionError, Agent, DomSnapshot, StepOutcome,
+    TranslateOptions, Translator, SELECTOR,
+};
+use common::{find_chrome, id_of, launch_browser, FixtureServer};
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+/// The page content sent with the most recent request.
+fn last_page(messages: &[Message]) -> &str {
+    messages
+        .iter()
+        .rev()
+        .find(|message| message.content.contains("PAGE CONTENT:"))
+        .map_or("", |message| message.content.as_str())
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn follows_a_link_and_reports_the_goal() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let backend = MockBackend::from_fn(|messages| {
+        let page = last_page(messages);
+
+        if page.contains("/article.html\nPAGE CONTENT") {
+            return String::from("DONE \"The secret word is pineapple.\"");
+        }
+
+        format!("CLICK {}", id_of(page, "href=article.html").unwrap())
+    });
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(backend)
+        .goal("Find the secret word.")
+        .start_url(server.url("index.html").parse().unwrap())
+        .max_steps(5)
+        .build()
+        .await
+        .unwrap();
+
+    let outcome = agent.run().await.unwrap();
+
+    assert!(
+        matches!(outcome, StepOutcome::Finished(goal) if goal == "The secret word is pineapple.")
+    );
+    assert_eq!(agent.steps(), 2);
+    assert!(agent
+        .page()
+        .url()
+        .await
+        .unwrap()
+        .unwrap()
+        .ends_with("/article.html"));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn keeps_going_after_progress_reports_on_a_fixed_goal() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(MockBackend::scripted([
+            "GOAL \"Found the article link.\"",
+            "DONE \"The secret word is pineapple.\"",
+        ]))
+        .goal("Find the secret word.")
+        .start_url(server.url("index.html").parse().unwrap())
+        .build()
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.step().await.unwrap(),
+        StepOutcome::Performed(Action::Goal(progress)) if progress == "Found the article link."
+    ));
+    assert!(matches!(
+        agent.step().await.unwrap(),
+        StepOutcome::Finished(result) if result == "The secret word is pineapple."
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn ends_the_run_when_an_unlocked_goal_is_reported() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(MockBackend::scripted(["GOAL \"Visit the article.\""]))
+        .start_url(server.url("index.html").parse().unwrap())
+        .build()
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.step().await.unwrap(),
+        StepOutcome::Finished(goal) if goal == "Visit the article."
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn types_into_an_input() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let backend = MockBackend::from_fn(|messages| {
+        let page = last_page(messages);
+
+        if page.contains("results.html") {
+            return String::from("DONE \"Searched.\"");
+        }
+
+        format!("TYPE {} \"pineapple\"", id_of(page, "<input").unwrap())
+    });
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(backend)
+        .goal("Search for pineapple.")
+        .start_url(server.url("index.html").parse().unwrap())
+        .max_steps(5)
+        .build()
+        .await
+        .unwrap();
+
+    let outcome = agent.step().await.unwrap();
+    assert!(
+        matches!(outcome, StepOutcome::Performed(Action::Type(_, text)) if text == "pineapple")
+    );
+
+    agent.run().await.unwrap();
+    assert!(agent
+        .page()
+        .url()
+        .await
+        .unwrap()
+        .unwrap()
+        .ends_with("/results.html?q=pineapple"));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn reports_unknown_elements_back_to_the_model() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let backend = MockBackend::scripted(["CLICK 99", "DONE \"Gave up.\""]);
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(backend.clone())
+        .goal("Click something.")
+        .start_url(server.url("index.html").parse().unwrap())
+        .build()
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.step().await.unwrap(),
+        StepOutcome::Failed(_)
+    ));
+    assert!(matches!(
+        agent.step().await.unwrap(),
+        StepOutcome::Finished(goal) if goal == "Gave up."
+    ));
+
+    let requests = backend.requests();
+    assert!(requests[1].iter().any(|message| message
+        .content
+        .contains("element 99 does not exist; valid ids are 0-")));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn stops_at_the_step_limit() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(MockBackend::from_fn(|_| String::from("SCROLL DOWN")))
+        .start_url(server.url("index.html").parse().unwrap())
+        .max_steps(2)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.run().await.unwrap(),
+        StepOutcome::StepLimitReached
+    ));
+    assert_eq!(agent.steps(), 2);
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn stops_when_the_token_budget_is_exhausted() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(MockBackend::from_fn(|_| String::from("SCROLL DOWN")))
+        .start_url(server.url("index.html").parse().unwrap())
+        .max_total_tokens(1)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.run().await.unwrap(),
+        StepOutcome::TokenBudgetExhausted
+    ));
+    assert_eq!(agent.steps(), 1);
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn describes_the_accessibility_tree() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let page = browser.new_page(server.url("widgets.html")).await.unwrap();
+    Settler::start(&page, SettleOptions::default())
+        .await
+        .unwrap()
+        .wait()
+        .await;
+
+    let viewport = browser::viewport(&page).await.unwrap();
+    let (snapshot, page_content) =
+        translate_accessibility(&page, &viewport, &TranslateOptions::default())
+            .await
+            .unwrap();
+
+    assert!(page_content.contains("<heading level=2>Preferences</heading>"));
+    assert!(page_content.contains("checked=true>Subscribe to the newsletter</checkbox>"));
+    assert!(page_content.contains(">Favourite colour</combobox>"));
+    assert!(page_content.contains(">Comments</textbox>"));
+    assert!(page_content.contains(">Save preferences</button>"));
+    assert_eq!(snapshot.len(), 4);
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn clicks_aria_buttons_with_the_accessibility_translator() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let backend = MockBackend::from_fn(|messages| {
+        format!(
+            "CLICK {}",
+            id_of(last_page(messages), "Save preferences").unwrap()
+        )
+    });
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(backend)
+        .goal("Save the preferences.")
+        .start_url(server.url("widgets.html").parse().unwrap())
+        .translate_options(TranslateOptions {
+            translator: Translator::Accessibility,
+            ..TranslateOptions::default()
+        })
+        .build()
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.step().await.unwrap(),
+        StepOutcome::Performed(Action::Click(_))
+    ));
+
+    let status = agent
+        .page()
+        .find_element("#status")
+        .await
+        .unwrap()
+        .inner_text()
+        .await
+        .unwrap();
+    assert_eq!(status.as_deref(), Some("Saved"));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn keeps_element_ids_when_the_page_changes() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let page = browser.new_page(server.url("index.html")).await.unwrap();
+    Settler::start(&page, SettleOptions::default())
+        .await
+        .unwrap()
+        .wait()
+        .await;
+
+    let id_of_button = |snapshot: &DomSnapshot| {
+        snapshot
+            .elements
+            .iter()
+            .find(|element| element.text.as_deref() == Some("Press me"))
+            .map(|element| element.id)
+            .unwrap()
+    };
+
+    let before = DomSnapshot::capture(&page, SELECTOR).await.unwrap();
+    let id = id_of_button(&before);
+
+    page.evaluate("document.body.prepend(document.createElement('button'))")
+        .await
+        .unwrap();
+    let after = DomSnapshot::capture(&page, SELECTOR).await.unwrap();
+    assert_eq!(id_of_button(&after), id);
+    assert_eq!(after.len(), before.len() + 1);
+
+    page.evaluate("document.querySelector('button:last-of-type').remove()")
+        .await
+        .unwrap();
+    assert!(matches!(
+        after.resolve(&page, id).await,
+        Err(ActionError::StaleElement(stale)) if stale == id
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn fills_in_form_controls() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let backend = MockBackend::from_fn(|messages| {
+        let page = last_page(messages);
+
+        if page.contains("checked=true") {
+            format!("UNCHECK {}", id_of(page, "type=checkbox").unwrap())
+        } else if !page.contains("value=\"Green\"") {
+            format!("SELECT {} \"green\"", id_of(page, "<select").unwrap())
+        } else if page.contains("value=\"Draft\"") {
+            format!("CLEAR {}", id_of(page, "<textarea").unwrap())
+        } else {
+            String::from("DONE \"Done.\"")
+        }
+    });
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(backend)
+        .goal("Fill in the preferences.")
+        .start_url(server.url("widgets.html").parse().unwrap())
+        .max_steps(5)
+        .build()
+        .await
+        .unwrap();
+
+    agent
+        .page()
+        .evaluate("document.querySelector('textarea').value = 'Draft'")
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.run().await.unwrap(),
+        StepOutcome::Finished(_)
+    ));
+
+    let state: Vec<String> = agent
+        .page()
+        .evaluate(
+            "[String(document.querySelector('input').checked), \
+             document.querySelector('select').value, \
+             document.querySelector('textarea').value]",
+        )
+        .await
+        .unwrap()
+        .into_value()
+        .unwrap();
+    assert_eq!(state, ["false", "Green", ""]);
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn detects_hidden_and_obscured_elements() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let page = browser.new_page(server.url("banner.html")).await.unwrap();
+    Settler::start(&page, SettleOptions::default())
+        .await
+        .unwrap()
+        .wait()
+        .await;
+
+    let snapshot = DomSnapshot::capture(&page, SELECTOR).await.unwrap();
+    let find = |text: &str| {
+        snapshot
+            .elements
+            .iter()
+            .find(|element| element.text.as_deref() == Some(text))
+            .unwrap()
+    };
+
+    assert!(find("Covered").visible && find("Covered").obscured);
+    assert!(!find("Invisible").visible);
+    assert!(find("Accept").visible && !find("Accept").obscured);
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn leaves_an_attached_browser_running() {
+    let (mut owner, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let address = owner.websocket_address().clone();
+    let agent = Agent::builder()
+        .attached_browser(browser::connect(&address).await.unwrap())
+        .backend(MockBackend::scripted(["GOAL \"Done.\""]))
+        .start_url(server.url("index.html").parse().unwrap())
+        .build()
+        .await
+        .unwrap();
+    agent.close().await.unwrap();
+
+    let page = owner.new_page(server.url("index.html")).await.unwrap();
+    assert!(page.url().await.unwrap().unwrap().ends_with("/index.html"));
+    owner.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn reports_browsers_that_cannot_be_reached() {
+    assert!(
+        browser::connect("ws://127.0.0.1:1/devtools/browser/missing")
+            .await
+            .is_err()
+    );
+    assert!(browser::connect("http://127.0.0.1:1").await.is_err());
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn emulates_the_given_device() {
+    let executable = find_chrome();
+    let profile = TempDir::new().unwrap();
+
+    let options = LaunchOptions {
+        viewport: ScreenSize {
+            width: 390,
+            height: 844,
+        },
+        device_scale_factor: Some(3.0),
+        mobile: true,
+        locale: Some(String::from("es-ES")),
+        timezone: Some(String::from("Asia/Tokyo")),
+        user_agent: Some(String::from("run-wild-test")),
+        args: vec![String::from("--no-sandbox")],
+        ..LaunchOptions::default()
+    };
+    let mut browser = browser::init(
+        Some(&executable),
+        Path::new("./browser"),
+        profile.path(),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let page = browser.new_page("about:blank").await.unwrap();
+    let device: serde_json::Value = page
+        .evaluate("[innerWidth, devicePixelRatio, navigator.language, navigator.userAgent]")
+        .await
+        .unwrap()
+        .into_value()
+        .unwrap();
+
+    assert_eq!(
+        device,
+        serde_json::json!([390, 3, "es-ES", "run-wild-test"])
+    );
+
+    // Chrome ignores TZ on Windows.
+    if !cfg!(windows) {
+        let timezone: String = page
+            .evaluate("Intl.DateTimeFormat().resolvedOptions().timeZone")
+            .await
+            .unwrap()
+            .into_value()
+            .unwrap();
+        assert_eq!(timezone, "Asia/Tokyo");
+    }
+    browser.close().await.unwrap();
+    browser.wait().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn waits_for_the_page_to_settle() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+    let options = SettleOptions {
+        max_wait: Duration::from_secs(2),
+        idle_time: Duration::from_millis(200),
+        max_in_flight: 0,
+    };
+
+    let page = browser.new_page(server.url("index.html")).await.unwrap();
+    let started = Instant::now();
+    let settled = Settler::start(&page, options).await.unwrap().wait().await;
+    assert_eq!(settled, Settled::Quiet);
+    assert!(started.elapsed() < options.max_wait);
+
+    let page = browser.new_page(server.url("busy.html")).await.unwrap();
+    let settled = Settler::start(&page, options).await.unwrap().wait().await;
+    assert!(matches!(
+        settled,
+        Settled::MaxWaitReached {
+            dom_changing: true,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+#[ignore = "needs Chrome or Chromium, run with --ignored"]
+async fn follows_links_into_new_tabs() {
+    let (browser, _profile) = launch_browser().await;
+    let server = FixtureServer::start().await;
+
+    let backend = MockBackend::from_fn(|messages| {
+        let page = last_page(messages);
+
+        if messages
+            .iter()
+            .any(|message| message.content == "CLOSETAB 1")
+        {
+            return String::from("DONE \"Read the article.\"");
+        }
+        if page.contains("TABS:") {
+            return String::from("CLOSETAB 1");
+        }
+
+        format!("CLICK {}", id_of(page, "href=article.html").unwrap())
+    });
+
+    let mut agent = Agent::builder()
+        .browser(browser)
+        .backend(backend.clone())
+        .goal("Read the article.")
+        .start_url(server.url("popup.html").parse().unwrap())
+        .max_steps(5)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        agent.run().await.unwrap(),
+        StepOutcome::Finished(_)
+    ));
+    assert_eq!(agent.steps(), 3);
+    assert_eq!(agent.pages().count(), 1);
+
+    let requests = backend.requests();
+    let tabs = last_page(&requests[1]);
+    assert!(tabs.contains("/article.html\nPAGE CONTENT: TABS:\n[0] \"Fixture Popup Opener\""));
+    assert!(tabs.contains("[1] \"Fixture Article\""));
+    assert!(tabs.contains("/article.html current\n"));
+    assert!(!last_page(&requests[2]).contains("TABS:"));
+}
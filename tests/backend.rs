@@ -0,0 +1,321 @@
+mod common;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use browser_agent::backend::{
+    AnthropicBackend, ChatBackend, Completion, LocalBackend, Message, ModelConfig, OpenAiBackend,
+    Price, RetryPolicy, RetryingBackend, Role, TransientError, Usage,
+};
+use common::ApiServer;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+const COMPLETION: &str = r#"{"choices": [{"message": {"role": "assistant", "content": "CLICK 1"}}], "usage": {"prompt_tokens": 10, "completion_tokens": 2}}"#;
+
+const POLICY: RetryPolicy = RetryPolicy {
+    max_retries: 3,
+    initial_delay: Duration::from_millis(1),
+    max_delay: Duration::from_millis(10),
+    request_timeout: Some(Duration::from_millis(200)),
+};
+
+fn messages() -> Vec<Message> {
+    vec![
+        Message::new(Role::System, "You are an agent controlling a browser."),
+        Message::new(Role::User, "PAGE CONTENT: <button id=1>Go</button>"),
+    ]
+}
+
+#[tokio::test]
+async fn retries_rate_limits_and_server_errors() {
+    let server = ApiServer::start(vec![
+        (
+            "429 Too Many Requests\r\nRetry-After: 0",
+            r#"{"error": {"message": "Slow down."}}"#,
+        ),
+        ("503 Service Unavailable", ""),
+        ("200 OK", COMPLETION),
+    ])
+    .await;
+    let backend = RetryingBackend::new(
+        OpenAiBackend::new("gpt-4").with_base_url(server.url()),
+        POLICY,
+    );
+
+    let completion = backend
+        .complete(&messages(), &ModelConfig::default())
+        .await
+        .unwrap();
+
+    assert_eq!(completion.content, "CLICK 1");
+    assert_eq!(
+        completion.usage,
+        Some(Usage {
+            prompt_tokens: 10,
+            completion_tokens: 2
+        })
+    );
+    assert_eq!(server.requests(), 3);
+}
+
+#[tokio::test]
+async fn honours_retry_after_dates() {
+    let server = ApiServer::start(vec![
+        (
+            "503 Service Unavailable\r\nRetry-After: Wed, 21 Oct 2015 07:28:00 GMT",
+            "",
+        ),
+        ("200 OK", COMPLETION),
+    ])
+    .await;
+    // Without the date, the backoff would wait at least 15 seconds.
+    let backend = RetryingBackend::new(
+        OpenAiBackend::new("gpt-4").with_base_url(server.url()),
+        RetryPolicy {
+            initial_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(30),
+            ..POLICY
+        },
+    );
+
+    let completion = tokio::time::timeout(
+        Duration::from_secs(5),
+        backend.complete(&messages(), &ModelConfig::default()),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(completion.content, "CLICK 1");
+    assert_eq!(server.requests(), 2);
+}
+
+#[tokio::test]
+async fn gives_up_after_the_last_retry() {
+    let server = ApiServer::start(vec![
+        ("529 Overloaded", r#"{"type": "error"}"#),
+        ("529 Overloaded", r#"{"type": "error"}"#),
+    ])
+    .await;
+    let backend = RetryingBackend::new(
+        AnthropicBackend::new("claude").with_base_url(server.url()),
+        RetryPolicy {
+            max_retries: 1,
+            ..POLICY
+        },
+    );
+
+    let error = backend
+        .complete(&messages(), &ModelConfig::default())
+        .await
+        .unwrap_err();
+
+    assert!(error.downcast_ref::<TransientError>().is_some());
+    assert_eq!(server.requests(), 2);
+}
+
+#[tokio::test]
+async fn does_not_retry_other_errors() {
+    let server = ApiServer::start(vec![
+        (
+            "401 Unauthorized",
+            r#"{"error": {"message": "Incorrect API key provided."}}"#,
+        ),
+        (
+            "429 Too Many Requests",
+            r#"{"error": {"type": "insufficient_quota"}}"#,
+        ),
+    ])
+    .await;
+    let backend = RetryingBackend::new(
+        OpenAiBackend::new("gpt-4").with_base_url(server.url()),
+        POLICY,
+    );
+
+    let error = backend
+        .complete(&messages(), &ModelConfig::default())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("Incorrect API key"));
+    assert_eq!(server.requests(), 1);
+
+    let error = backend
+        .complete(&messages(), &ModelConfig::default())
+        .await
+        .unwrap_err();
+    assert!(error.downcast_ref::<TransientError>().is_none());
+    assert_eq!(server.requests(), 2);
+}
+
+#[tokio::test]
+async fn tolerates_a_missing_usage() {
+    let server = ApiServer::start(vec![(
+        "200 OK",
+        r#"{"choices": [{"message": {"role": "assistant", "content": "BACK"}}]}"#,
+    )])
+    .await;
+    let backend = OpenAiBackend::new("gpt-4").with_base_url(server.url());
+
+    let completion = backend
+        .complete(&messages(), &ModelConfig::default())
+        .await
+        .unwrap();
+
+    assert_eq!(completion.content, "BACK");
+    assert_eq!(completion.usage, None);
+}
+
+#[tokio::test]
+async fn sends_the_model_config() {
+    let server = ApiServer::start(vec![
+        ("200 OK", COMPLETION),
+        (
+            "200 OK",
+            r#"{"message": {"role": "assistant", "content": "BACK"}}"#,
+        ),
+    ])
+    .await;
+    let config = ModelConfig {
+        model: Some(String::from("gpt-4o")),
+        temperature: Some(0.0),
+        max_tokens: 1000,
+        seed: Some(7),
+        stop: vec![String::from("\n")],
+        ..ModelConfig::default()
+    };
+
+    OpenAiBackend::new("gpt-4")
+        .with_base_url(server.url())
+        .complete(&messages(), &config)
+        .await
+        .unwrap();
+    LocalBackend::new("llama3")
+        .with_base_url(server.url())
+        .complete(&messages(), &ModelConfig::default())
+        .await
+        .unwrap();
+
+    let bodies = server.bodies();
+    let openai: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+    assert_eq!(openai["model"], "gpt-4o");
+    assert_eq!(openai["temperature"], 0.0);
+    assert_eq!(openai["max_tokens"], 1000);
+    assert_eq!(openai["seed"], 7);
+    assert_eq!(openai["stop"], serde_json::json!(["\n"]));
+    assert!(openai.get("top_p").is_none());
+
+    let local: serde_json::Value = serde_json::from_str(&bodies[1]).unwrap();
+    assert_eq!(local["model"], "llama3");
+    assert_eq!(local["options"]["num_predict"], 500);
+    assert!(local["options"].get("stop").is_none());
+}
+
+#[test]
+fn reads_the_model_config_from_toml() {
+    let config: ModelConfig =
+        toml::from_str("temperature = 0.0\nseed = 42\nstop = [\"END\"]").unwrap();
+
+    assert_eq!(
+        config,
+        ModelConfig {
+            temperature: Some(0.0),
+            seed: Some(42),
+            stop: vec![String::from("END")],
+            ..ModelConfig::default()
+        }
+    );
+    assert!(toml::from_str::<ModelConfig>("temprature = 0.0").is_err());
+}
+
+/// Takes too long to answer the first request, then answers straight away.
+#[derive(Debug, Default)]
+struct SlowStart {
+    attempts: AtomicUsize,
+}
+
+#[async_trait]
+impl ChatBackend for SlowStart {
+    async fn complete(&self, _messages: &[Message], _config: &ModelConfig) -> Result<Completion> {
+        if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        }
+
+        Ok(Completion {
+            content: String::from("RELOAD"),
+            usage: None,
+        })
+    }
+}
+
+#[tokio::test]
+async fn retries_requests_that_time_out() {
+    let backend = RetryingBackend::new(SlowStart::default(), POLICY);
+
+    let completion = backend
+        .complete(&messages(), &ModelConfig::default())
+        .await
+        .unwrap();
+
+    assert_eq!(completion.content, "RELOAD");
+}
+
+#[test]
+fn backs_off_exponentially_with_jitter() {
+    let policy = RetryPolicy {
+        max_retries: 5,
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(10),
+        request_timeout: None,
+    };
+
+    for retry in 0..5 {
+        let full = Duration::from_secs(1 << retry).min(policy.max_delay);
+        let delay = policy.delay(retry, None);
+        assert!(
+            delay >= full / 2 && delay <= full,
+            "{delay:?} for retry {retry}"
+        );
+    }
+
+    assert_eq!(
+        policy.delay(0, Some(Duration::from_secs(7))),
+        Duration::from_secs(7)
+    );
+    assert_eq!(
+        policy.delay(0, Some(Duration::from_secs(120))),
+        policy.max_delay
+    );
+}
+
+#[test]
+fn prices_usage_by_model() {
+    assert_eq!(
+        Price::of_model("gpt-4o-mini-2024-07-18"),
+        Some(Price::new(0.15, 0.6))
+    );
+    assert_eq!(Price::of_model("gpt-4-0613"), Some(Price::new(30.0, 60.0)));
+    assert_eq!(Price::of_model("llama3"), None);
+
+    let usage = Usage {
+        prompt_tokens: 10_000,
+        completion_tokens: 1_000,
+    };
+    let cost = usage.cost(Price::of_model("gpt-4").unwrap());
+    assert!((cost - 0.36).abs() < 1e-9);
+    assert_eq!(OpenAiBackend::new("gpt-4").model(), Some("gpt-4"));
+}
+
+#[test]
+fn usage_saturates_instead_of_overflowing() {
+    let mut usage = Usage {
+        prompt_tokens: u32::MAX - 1,
+        completion_tokens: 2,
+    };
+    assert_eq!(usage.total_tokens(), u32::MAX);
+
+    usage += usage;
+    assert_eq!(usage.prompt_tokens, u32::MAX);
+    assert_eq!(usage.total_tokens(), u32::MAX);
+}
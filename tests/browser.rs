@@ -0,0 +1,67 @@
+use browser_agent::browser::{self, LaunchOptions, ScreenSize};
+use std::path::Path;
+use tempfile::TempDir;
+
+#[test]
+fn finds_the_browser_in_the_chrome_variable() {
+    let dir = TempDir::new().unwrap();
+    let executable = dir.path().join("my-chrome");
+    std::fs::write(&executable, "").unwrap();
+
+    assert_eq!(
+        browser::find_chrome_in(Some(&executable), None, &[]),
+        Some(executable.clone())
+    );
+
+    // A variable pointing nowhere is skipped, rather than launched and failing later.
+    let missing = dir.path().join("missing");
+    assert_eq!(
+        browser::find_chrome_in(Some(&missing), None, &[&executable]),
+        Some(executable)
+    );
+}
+
+#[test]
+fn finds_the_browser_in_path() {
+    let dir = TempDir::new().unwrap();
+    let executable = dir
+        .path()
+        .join("chromium")
+        .with_extension(std::env::consts::EXE_EXTENSION);
+    std::fs::write(&executable, "").unwrap();
+
+    assert_eq!(
+        browser::find_chrome_in(None, Some(dir.path().as_os_str()), &[]),
+        Some(executable)
+    );
+    assert_eq!(browser::find_chrome_in(None, None, &[]), None);
+}
+
+#[tokio::test]
+async fn rejects_a_missing_executable() {
+    let dir = TempDir::new().unwrap();
+
+    let error = browser::init(
+        Some(&dir.path().join("chrome")),
+        Path::new("./browser"),
+        dir.path(),
+        &LaunchOptions::default(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(error.to_string().starts_with("There is no browser at"));
+}
+
+#[test]
+fn parses_screen_sizes() {
+    assert_eq!(
+        "390x844".parse(),
+        Ok(ScreenSize {
+            width: 390,
+            height: 844
+        })
+    );
+    assert!("390".parse::<ScreenSize>().is_err());
+    assert!("wide x tall".parse::<ScreenSize>().is_err());
+}
@@ -0,0 +1,57 @@
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// Runs the CLI with the given arguments, pointing it at a browser that doesn't exist so it stops before launching one.
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_browser-agent"))
+        .args(["--chrome-path", "/nonexistent/chrome"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn reads_model_and_browser_settings_from_the_config_file() {
+    let dir = TempDir::new().unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(
+        &config,
+        "temperature = 0.0\nseed = 1\nephemeral = true\nbrowser_dir = \"browser\"\n",
+    )
+    .unwrap();
+
+    let output = run(&["--config", config.to_str().unwrap(), "-vv"]);
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("There is no browser at /nonexistent/chrome"));
+
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(log.contains("temperature: Some(0.0)"), "{log}");
+    assert!(log.contains("seed: Some(1)"), "{log}");
+    assert!(log.contains("the browser directory browser and"), "{log}");
+    assert!(log.contains("run-wild-"), "{log}");
+}
+
+#[test]
+fn rejects_unknown_config_keys() {
+    let dir = TempDir::new().unwrap();
+    let config = dir.path().join("config.toml");
+    std::fs::write(&config, "temprature = 0.0\n").unwrap();
+
+    let output = run(&["--config", config.to_str().unwrap()]);
+
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Failed to parse"));
+}
+
+#[test]
+fn ephemeral_profiles_cannot_be_given_a_directory() {
+    let output = run(&["--ephemeral", "--user-data-dir", "profile"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("cannot be used with"));
+}
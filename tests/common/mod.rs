@@ -0,0 +1,223 @@
+//! Shared harness for the integration tests: a static file server for the HTML fixtures, a scripted API server, and a headless Chromium to load them in.
+#![allow(dead_code)]
+
+use browser_agent::browser::{self, LaunchOptions};
+use chromiumoxide::Browser;
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// Serves the files in `tests/fixtures` over HTTP on a random local port.
+pub struct FixtureServer {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl FixtureServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream));
+            }
+        });
+
+        Self { addr, task }
+    }
+
+    /// The URL of the given fixture, like `index.html`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/{path}", self.addr)
+    }
+}
+
+impl Drop for FixtureServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(mut stream: TcpStream) {
+    let mut buffer = vec![0; 8192];
+    let Ok(read) = stream.read(&mut buffer).await else {
+        return;
+    };
+
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .split('?')
+        .next()
+        .unwrap_or("/")
+        .trim_start_matches('/');
+
+    let file = fixtures_dir().join(if path.is_empty() { "index.html" } else { path });
+    let body = if path.contains("..") {
+        None
+    } else {
+        tokio::fs::read(&file).await.ok()
+    };
+
+    let response = match body {
+        Some(body) => [
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes(),
+            body,
+        ]
+        .concat(),
+        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+    };
+
+    let _ = stream.write_all(&response).await;
+}
+
+/// Answers HTTP requests with each of the given responses in turn, like a model API that misbehaves on cue.
+pub struct ApiServer {
+    addr: SocketAddr,
+    bodies: Arc<Mutex<Vec<String>>>,
+    task: JoinHandle<()>,
+}
+
+impl ApiServer {
+    /// Starts the server. Each response is a status line and headers, like `429 Too Many Requests\r\nRetry-After: 0`, and a body.
+    pub async fn start(responses: Vec<(&'static str, &'static str)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+
+        let received = bodies.clone();
+        let task = tokio::spawn(async move {
+            for (head, body) in responses {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let request = read_request(&mut stream).await;
+                received.lock().unwrap().push(request);
+
+                let response = format!(
+                    "HTTP/1.1 {head}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        Self { addr, bodies, task }
+    }
+
+    /// The base URL of the API.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The number of requests answered so far.
+    pub fn requests(&self) -> usize {
+        self.bodies.lock().unwrap().len()
+    }
+
+    /// The bodies of the requests answered so far.
+    pub fn bodies(&self) -> Vec<String> {
+        self.bodies.lock().unwrap().clone()
+    }
+}
+
+impl Drop for ApiServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Reads a whole request and returns its body, so the client doesn't see the connection reset while it's still sending it.
+async fn read_request(stream: &mut TcpStream) -> String {
+    let mut request = Vec::new();
+    let mut buffer = vec![0; 8192];
+
+    loop {
+        let Ok(read) = stream.read(&mut buffer).await else {
+            return String::new();
+        };
+        if read == 0 {
+            return String::new();
+        }
+        request.extend_from_slice(&buffer[..read]);
+
+        let text = String::from_utf8_lossy(&request);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = head
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+
+            if body.len() >= length {
+                return body.to_string();
+            }
+        }
+    }
+}
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Finds the Chrome or Chromium executable for the browser tests, which are ignored unless run with `--ignored`.
+///
+/// Set `CHROME` to point at a specific executable. Panics if none is installed, so a run that asked for
+/// the browser tests never passes without them.
+pub fn find_chrome() -> PathBuf {
+    browser::find_chrome()
+        .expect("no Chrome or Chromium found, set CHROME to run the browser tests")
+}
+
+/// Launches a headless Chromium with a throwaway profile, through the same [`browser::init`] the agent uses.
+///
+/// The profile is deleted when the `TempDir` is dropped.
+pub async fn launch_browser() -> (Browser, TempDir) {
+    let profile = TempDir::new().unwrap();
+    let options = LaunchOptions {
+        args: vec![String::from("--no-sandbox")],
+        ..LaunchOptions::default()
+    };
+
+    let browser = browser::init(
+        Some(&find_chrome()),
+        profile.path(),
+        profile.path(),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    (browser, profile)
+}
+
+/// Finds the id of the first line of a page summary containing `needle`.
+pub fn id_of(page_content: &str, needle: &str) -> Option<usize> {
+    page_content
+        .lines()
+        .find(|line| line.contains(needle))?
+        .split("id=")
+        .nth(1)?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,297 @@
+use browser_agent::{
+    backend::{MockBackend, Role},
+    Action, ActionError, Conversation, HistoryPolicy, ParseError, Protocol, RunReport, Usage,
+};
+
+const URL: &str = "https://example.com/";
+
+#[tokio::test]
+async fn parses_scripted_replies_into_actions() {
+    let backend = MockBackend::scripted(["CLICK 3", "TYPE 1 \"hello world\"", "BACK"]);
+    let mut conversation = Conversation::new().with_backend(backend);
+
+    assert_eq!(
+        conversation.request_action(URL, "").await.unwrap(),
+        Action::Click(3)
+    );
+    assert_eq!(
+        conversation.request_action(URL, "").await.unwrap(),
+        Action::Type(1, String::from("hello world"))
+    );
+    assert_eq!(
+        conversation.request_action(URL, "").await.unwrap(),
+        Action::Back
+    );
+    assert!(conversation.request_action(URL, "").await.is_err());
+}
+
+#[tokio::test]
+async fn sends_the_goal_url_and_page_content() {
+    let backend = MockBackend::scripted(["CLICK 0"]);
+    let mut conversation =
+        Conversation::with_goal("Buy a plane ticket.").with_backend(backend.clone());
+
+    conversation
+        .request_action(URL, "<button id=0>Buy</button>")
+        .await
+        .unwrap();
+
+    let request = &backend.requests()[0];
+    assert_eq!(request[0].role, Role::System);
+    assert!(request[0].content.contains("which you cannot change"));
+    assert!(request[0]
+        .content
+        .contains("GOAL \"TEXT\" - Reports your progress towards the objective, and carries on."));
+    assert!(request[0]
+        .content
+        .contains("DONE \"TEXT\" - Reports the result once the objective is complete"));
+    assert_eq!(
+        request[1].content,
+        "OBJECTIVE: Buy a plane ticket.\nCURRENT URL: https://example.com/\nPAGE CONTENT: <button id=0>Buy</button>"
+    );
+}
+
+#[tokio::test]
+async fn only_unlocked_goals_can_be_replaced() {
+    let mut wild =
+        Conversation::new().with_backend(MockBackend::scripted(["GOAL \"Read the news.\""]));
+    wild.request_action(URL, "").await.unwrap();
+    assert_eq!(wild.goal(), "Read the news.");
+
+    let mut locked = Conversation::with_goal("Find a recipe.")
+        .with_backend(MockBackend::scripted(["GOAL \"Opened a cookbook.\""]));
+    let action = locked.request_action(URL, "").await.unwrap();
+    assert_eq!(action, Action::Goal(String::from("Opened a cookbook.")));
+    assert_eq!(locked.goal(), "Find a recipe.");
+    assert_eq!(locked.progress(), ["Opened a cookbook."]);
+}
+
+#[tokio::test]
+async fn done_reports_the_result_without_touching_the_goal() {
+    let mut locked =
+        Conversation::with_goal("Find a recipe.").with_backend(MockBackend::scripted([
+            "GOAL \"Opened a cookbook.\"",
+            "DONE \"Pancakes.\"",
+        ]));
+
+    locked.request_action(URL, "").await.unwrap();
+    let action = locked.request_action(URL, "").await.unwrap();
+
+    assert_eq!(action, Action::Done(String::from("Pancakes.")));
+    assert_eq!(locked.goal(), "Find a recipe.");
+    assert_eq!(locked.progress(), ["Opened a cookbook."]);
+}
+
+#[tokio::test]
+async fn computes_replies_from_the_prompt() {
+    let backend = MockBackend::from_fn(|messages| {
+        if messages.last().unwrap().content.contains("<input id=7>") {
+            String::from("TYPE 7 \"rust\"")
+        } else {
+            String::from("SCROLL DOWN")
+        }
+    });
+    let mut conversation = Conversation::new().with_backend(backend);
+
+    assert_eq!(
+        conversation
+            .request_action(URL, "<input id=7>Search</input>")
+            .await
+            .unwrap(),
+        Action::Type(7, String::from("rust"))
+    );
+    assert_eq!(
+        conversation
+            .request_action(URL, "<p id=0>Nothing here</p>")
+            .await
+            .unwrap(),
+        Action::Scroll(browser_agent::ScrollDirection::Down)
+    );
+}
+
+#[tokio::test]
+async fn unparseable_replies_are_recoverable_errors() {
+    let backend = MockBackend::scripted(["I'm not sure what to do.", "RELOAD"]);
+    let mut conversation = Conversation::new().with_backend(backend.clone());
+
+    let error = conversation.request_action(URL, "").await.unwrap_err();
+    let error = error.downcast::<ActionError>().unwrap();
+    assert!(matches!(
+        error,
+        ActionError::Parse(ParseError::UnknownCommand(_))
+    ));
+
+    conversation.report_error(&error);
+    conversation.request_action(URL, "").await.unwrap();
+
+    let request = &backend.requests()[1];
+    assert!(request.iter().any(|message| message.role == Role::User
+        && message
+            .content
+            .starts_with("ERROR: could not parse your reply")));
+}
+
+#[tokio::test]
+async fn json_protocol_includes_the_schema() {
+    let backend = MockBackend::scripted([r#"{"action": "goto", "url": "https://example.org"}"#]);
+    let mut conversation = Conversation::new()
+        .with_backend(backend.clone())
+        .with_protocol(Protocol::Json);
+
+    let action = conversation.request_action(URL, "").await.unwrap();
+    assert_eq!(
+        action,
+        Action::Navigate("https://example.org".parse().unwrap())
+    );
+    assert!(backend.requests()[0][0].content.contains("\"oneOf\""));
+}
+
+#[tokio::test]
+async fn keeps_actions_but_drops_old_pages() {
+    let backend = MockBackend::scripted(["CLICK 0", "BACK", "BACK"]);
+    let mut conversation = Conversation::new().with_backend(backend.clone());
+
+    conversation
+        .request_action(URL, "<button id=0>First</button>")
+        .await
+        .unwrap();
+    conversation
+        .request_action("https://example.com/about", "<p id=0>Second</p>")
+        .await
+        .unwrap();
+    conversation
+        .request_action("https://example.org/", "<p id=0>Third</p>")
+        .await
+        .unwrap();
+
+    let requests = backend.requests();
+    let lengths = requests.iter().map(Vec::len).collect::<Vec<_>>();
+    assert_eq!(lengths, [2, 4, 6]);
+
+    let last = &requests[2];
+    assert!(last[1].content.starts_with(
+        "OBJECTIVE: Visit 10 webpages.\nCURRENT URL: https://example.com/\nPAGE CONTENT: [left out"
+    ));
+    assert_eq!(last[2].content, "CLICK 0");
+    assert!(last[3]
+        .content
+        .ends_with("[left out, this page is no longer shown]"));
+    assert!(last[5].content.ends_with("PAGE CONTENT: <p id=0>Third</p>"));
+}
+
+#[tokio::test]
+async fn drops_the_oldest_steps_when_the_history_is_too_long() {
+    let backend = MockBackend::from_fn(|_| String::from("SCROLL DOWN"));
+    let mut conversation = Conversation::new()
+        .with_backend(backend.clone())
+        .with_history_policy(HistoryPolicy {
+            max_tokens: 80,
+            ..HistoryPolicy::default()
+        });
+
+    for page in ["one", "two", "three", "four", "five", "six"] {
+        conversation.request_action(URL, page).await.unwrap();
+    }
+
+    // Without a limit, the last request would hold the system prompt and 11 messages.
+    let last = backend.requests().pop().unwrap();
+    assert!(last.len() < 12);
+    assert!(last[1].content.starts_with("OBJECTIVE:"));
+    assert!(last.last().unwrap().content.ends_with("PAGE CONTENT: six"));
+}
+
+#[tokio::test]
+async fn keeps_earlier_steps_next_to_a_large_page() {
+    let backend = MockBackend::from_fn(|_| String::from("SCROLL DOWN"));
+    let mut conversation = Conversation::new()
+        .with_backend(backend.clone())
+        .with_history_policy(HistoryPolicy {
+            max_tokens: 200,
+            full_pages: 0,
+            ..HistoryPolicy::default()
+        });
+    let large_page = "<p id=0>lorem ipsum</p>".repeat(100);
+
+    conversation.request_action(URL, "one").await.unwrap();
+    conversation.request_action(URL, "two").await.unwrap();
+    conversation.request_action(URL, &large_page).await.unwrap();
+
+    // The system prompt, two earlier steps of two messages each, and the large page in full.
+    let last = backend.requests().pop().unwrap();
+    assert_eq!(last.len(), 6);
+    assert_eq!(last[2].content, "SCROLL DOWN");
+    assert!(last[5].content.ends_with(&large_page));
+}
+
+#[tokio::test]
+async fn summarises_the_steps_that_were_dropped() {
+    let backend = MockBackend::from_fn(|messages| {
+        if messages[0].content.starts_with("You summarise") {
+            String::from("The agent scrolled down a few times.")
+        } else {
+            String::from("SCROLL DOWN")
+        }
+    });
+    let mut conversation = Conversation::new()
+        .with_backend(backend.clone())
+        .with_history_policy(HistoryPolicy {
+            max_tokens: 80,
+            summarize: true,
+            ..HistoryPolicy::default()
+        });
+
+    for page in ["one", "two", "three", "four", "five", "six"] {
+        conversation.request_action(URL, page).await.unwrap();
+    }
+
+    assert_eq!(
+        conversation.summary(),
+        Some("The agent scrolled down a few times.")
+    );
+
+    let last = backend.requests().pop().unwrap();
+    assert_eq!(
+        last[1].content,
+        "SUMMARY OF EARLIER STEPS: The agent scrolled down a few times."
+    );
+}
+
+#[tokio::test]
+async fn accumulates_usage_across_requests() {
+    let backend = MockBackend::scripted(["CLICK 1", "GOAL \"Done here.\""]);
+    let mut conversation = Conversation::with_goal("Count.").with_backend(backend);
+
+    conversation.request_action(URL, "one two").await.unwrap();
+    let first = conversation.usage();
+    assert_eq!(first.completion_tokens, 2);
+
+    conversation.request_action(URL, "three").await.unwrap();
+    let second = conversation.usage();
+    assert_eq!(second.completion_tokens, 5);
+    assert!(second.prompt_tokens > first.prompt_tokens);
+}
+
+#[tokio::test]
+async fn tracks_usage_per_step() {
+    let backend = MockBackend::scripted(["CLICK 1", "BACK"]);
+    let mut conversation = Conversation::new().with_backend(backend);
+
+    conversation.request_action(URL, "one").await.unwrap();
+    conversation.request_action(URL, "two").await.unwrap();
+
+    let steps = conversation.step_usage();
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0].completion_tokens, 2);
+    assert_eq!(steps[1].completion_tokens, 1);
+
+    let mut total = Usage::default();
+    for step in steps {
+        total += *step;
+    }
+    assert_eq!(total, conversation.usage());
+
+    let report = RunReport::new(2, &conversation);
+    assert_eq!(report.usage, conversation.usage());
+    assert_eq!(report.cost, None);
+    assert!(report.to_string().starts_with("Took 2 steps and used"));
+}
@@ -0,0 +1,245 @@
+use browser_agent::{
+    browser::Viewport, count_tokens, translate, Bounds, DomSnapshot, SnapshotElement,
+    TranslateOptions, VisibilityFilter,
+};
+
+/// An element at the given distance from the top of the viewport.
+fn element(
+    id: usize,
+    tag: &str,
+    text: &str,
+    attributes: &[(&str, &str)],
+    y: f64,
+) -> SnapshotElement {
+    SnapshotElement {
+        id,
+        tag: tag.to_string(),
+        text: Some(text.to_string()),
+        attributes: attributes
+            .iter()
+            .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+            .collect(),
+        bounds: Some(Bounds {
+            x: 0.0,
+            y,
+            width: 100.0,
+            height: 20.0,
+        }),
+        visible: true,
+        ..SnapshotElement::default()
+    }
+}
+
+fn snapshot() -> DomSnapshot {
+    DomSnapshot {
+        elements: vec![
+            element(0, "P", "Welcome to the fixture site.", &[], 10.0),
+            element(
+                1,
+                "INPUT",
+                "",
+                &[("placeholder", "Search the fixtures")],
+                50.0,
+            ),
+            element(
+                2,
+                "A",
+                "Read the article",
+                &[("href", "article.html")],
+                100.0,
+            ),
+            element(3, "BUTTON", "Press me", &[], 150.0),
+            element(4, "IMG", "", &[("alt", "A pineapple")], 3000.0),
+            element(5, "IMG", "", &[], 3100.0),
+        ],
+    }
+}
+
+const VIEWPORT: Viewport = Viewport {
+    scroll_y: 0.0,
+    height: 800.0,
+    page_height: 4000.0,
+};
+
+#[test]
+fn translates_each_kind_of_element() {
+    let summary = translate(&snapshot(), &VIEWPORT, &TranslateOptions::default());
+
+    assert_eq!(
+        summary,
+        "<input id=1>Search the fixtures</input>\n\
+         <link id=2 href=article.html>Read the article<link>\n\
+         <button id=3>Press me</button>\n\
+         <img id=4 alt=\"A pineapple\"/>"
+    );
+}
+
+#[test]
+fn includes_paragraphs_when_asked() {
+    let options = TranslateOptions {
+        include_paragraphs: true,
+        ..TranslateOptions::default()
+    };
+
+    let summary = translate(&snapshot(), &VIEWPORT, &options);
+
+    assert!(summary.starts_with("<p id=0>Welcome to the fixture site.</p>\n"));
+}
+
+#[test]
+fn leaves_out_elements_far_from_the_viewport() {
+    let options = TranslateOptions {
+        viewport_only: true,
+        ..TranslateOptions::default()
+    };
+
+    let summary = translate(&snapshot(), &VIEWPORT, &options);
+
+    assert!(!summary.contains("A pineapple"));
+    assert!(
+        summary.ends_with("[2 more elements in the 4.0 screens below, scroll down to see them]")
+    );
+}
+
+#[test]
+fn describes_form_controls() {
+    let snapshot = DomSnapshot {
+        elements: vec![
+            SnapshotElement {
+                label: Some(String::from("Subscribe")),
+                checked: Some(false),
+                ..element(0, "INPUT", "", &[("type", "checkbox")], 0.0)
+            },
+            SnapshotElement {
+                label: Some(String::from("Favourite colour")),
+                value: Some(String::from("Red")),
+                options: vec![String::from("Red"), String::from("Green")],
+                ..element(1, "SELECT", "", &[], 0.0)
+            },
+            SnapshotElement {
+                value: Some(String::from("Great site")),
+                ..element(2, "TEXTAREA", "", &[("name", "comments")], 0.0)
+            },
+            SnapshotElement {
+                value: Some(String::from("2024-01-01")),
+                ..element(3, "INPUT", "", &[("type", "date"), ("name", "day")], 0.0)
+            },
+            SnapshotElement {
+                value: Some(String::from("Send")),
+                ..element(4, "INPUT", "", &[("type", "submit")], 0.0)
+            },
+            element(5, "INPUT", "", &[("type", "hidden")], 0.0),
+            element(6, "INPUT", "", &[], 0.0),
+        ],
+    };
+
+    let summary = translate(&snapshot, &VIEWPORT, &TranslateOptions::default());
+
+    assert_eq!(
+        summary,
+        "<input id=0 type=checkbox checked=false>Subscribe</input>\n\
+         <select id=1 value=\"Red\" options=[\"Red\",\"Green\"]>Favourite colour</select>\n\
+         <textarea id=2 value=\"Great site\">comments</textarea>\n\
+         <input id=3 type=date value=\"2024-01-01\">day</input>\n\
+         <button id=4>Send</button>\n\
+         <input id=6></input>"
+    );
+}
+
+#[test]
+fn marks_or_drops_hidden_and_obscured_elements() {
+    let snapshot = DomSnapshot {
+        elements: vec![
+            element(0, "BUTTON", "Accept", &[], 0.0),
+            SnapshotElement {
+                obscured: true,
+                ..element(1, "BUTTON", "Covered", &[], 0.0)
+            },
+            SnapshotElement {
+                visible: false,
+                ..element(2, "BUTTON", "Invisible", &[], 0.0)
+            },
+        ],
+    };
+
+    let filtered = |visibility| {
+        let options = TranslateOptions {
+            visibility,
+            ..TranslateOptions::default()
+        };
+        translate(&snapshot, &VIEWPORT, &options)
+    };
+
+    assert_eq!(
+        filtered(VisibilityFilter::Off),
+        "<button id=0>Accept</button>\n<button id=1>Covered</button>\n<button id=2>Invisible</button>"
+    );
+    assert_eq!(
+        filtered(VisibilityFilter::Mark),
+        "<button id=0>Accept</button>\n<button id=1 obscured>Covered</button>\n<button id=2 hidden>Invisible</button>"
+    );
+    assert_eq!(
+        filtered(VisibilityFilter::Drop),
+        "<button id=0>Accept</button>"
+    );
+}
+
+#[test]
+fn counts_tokens_like_gpt_4() {
+    assert_eq!(count_tokens("hello world"), 2);
+    assert_eq!(count_tokens(""), 0);
+}
+
+#[test]
+fn trims_the_least_important_elements_to_fit_the_budget() {
+    let mut elements: Vec<_> = (0..50)
+        .map(|id| {
+            element(
+                id,
+                "P",
+                "Some long paragraph of filler text that nobody needs.",
+                &[],
+                0.0,
+            )
+        })
+        .collect();
+    elements.push(element(
+        50,
+        "A",
+        "Read the article",
+        &[("href", "article.html")],
+        0.0,
+    ));
+    elements.push(element(51, "BUTTON", "Press me", &[], 0.0));
+
+    let options = TranslateOptions {
+        include_paragraphs: true,
+        max_tokens: Some(100),
+        ..TranslateOptions::default()
+    };
+    let summary = translate(&DomSnapshot { elements }, &VIEWPORT, &options);
+
+    assert!(count_tokens(&summary) <= 100);
+    assert!(summary.contains("<button id=51>Press me</button>"));
+    assert!(summary.contains("<link id=50 href=article.html>"));
+    assert!(summary.contains("<p id=0>"));
+    assert!(!summary.contains("<p id=49>"));
+    assert!(summary
+        .ends_with("less important elements were left out to keep the page description short]"));
+
+    // Lines that are kept stay in the order they appear on the page.
+    assert!(summary.find("<p id=0>") < summary.find("<button id=51>"));
+}
+
+#[test]
+fn leaves_pages_that_fit_untouched() {
+    let options = TranslateOptions {
+        max_tokens: Some(1000),
+        ..TranslateOptions::default()
+    };
+
+    assert_eq!(
+        translate(&snapshot(), &VIEWPORT, &options),
+        translate(&snapshot(), &VIEWPORT, &TranslateOptions::default())
+    );
+}